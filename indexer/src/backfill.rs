@@ -0,0 +1,260 @@
+//! Finds holes in the indexed chain, either heights we've never indexed or blocks that are
+//! missing transactions, and reindexes exactly those heights from the node.
+
+use crate::{backfill_block, rpc::BitcoinRpc, ProcessBlockError};
+use bitcoin::{hashes::Hash, BlockHash};
+use futures::{StreamExt, TryStreamExt};
+use tracing::info;
+
+pub async fn run(
+    database: &mut tokio_postgres::Client,
+    bitcoin_rpc: &BitcoinRpc,
+    from: Option<u64>,
+    verify_transactions: bool,
+    concurrency: usize,
+) -> Result<(), ProcessBlockError> {
+    let from = match from {
+        Some(from) => from as i64,
+        None => database
+            .query_one("SELECT COALESCE(MIN(height), 0) AS height FROM blocks", &[])
+            .await?
+            .get("height"),
+    };
+
+    let mut heights = Vec::new();
+
+    for (start, end) in find_missing_ranges(database, from).await? {
+        info!(start, end, "Missing blocks");
+        heights.extend(start..=end);
+    }
+
+    if verify_transactions {
+        for height in find_mismatched_blocks(database, bitcoin_rpc, from, concurrency).await? {
+            info!(
+                height,
+                "Block has a different transaction count to the node"
+            );
+            heights.push(height);
+        }
+    }
+
+    heights.sort_unstable();
+    heights.dedup();
+
+    let mut blocks = futures::stream::iter(heights.iter().copied())
+        .map(|height| async move {
+            let hash = bitcoin_rpc.get_block_hash(height as u64).await?;
+            let block = bitcoin_rpc.get_block(&hash).await?;
+
+            Ok::<_, ProcessBlockError>((height, hash, block))
+        })
+        .buffered(concurrency);
+
+    while let Some((height, hash, block)) = blocks.try_next().await? {
+        backfill_block(database, height, &hash, &block).await?;
+        info!(height, %hash, "Repaired block");
+    }
+
+    info!(count = heights.len(), "Finished repairing blocks");
+
+    Ok(())
+}
+
+/// Finds every inclusive range of heights above `from` that are missing from the database.
+async fn find_missing_ranges(
+    database: &tokio_postgres::Client,
+    from: i64,
+) -> Result<Vec<(i64, i64)>, tokio_postgres::Error> {
+    let query = "
+        SELECT gap_start, gap_end
+        FROM (
+            SELECT
+                height + 1 AS gap_start,
+                LEAD(height) OVER (ORDER BY height) - 1 AS gap_end
+            FROM blocks
+            WHERE height >= $1
+        ) gaps
+        WHERE gap_end >= gap_start
+        ORDER BY gap_start ASC
+    ";
+
+    let lowest: Option<i64> = database
+        .query_one(
+            "SELECT MIN(height) AS height FROM blocks WHERE height >= $1",
+            &[&from],
+        )
+        .await?
+        .get("height");
+
+    let mut ranges = Vec::new();
+
+    match lowest {
+        Some(lowest) if lowest > from => ranges.push((from, lowest - 1)),
+        _ => {}
+    }
+
+    ranges.extend(
+        database
+            .query(query, &[&from])
+            .await?
+            .into_iter()
+            .map(|row| (row.get("gap_start"), row.get("gap_end"))),
+    );
+
+    Ok(ranges)
+}
+
+/// Finds every block above `from` where the amount of transactions we've indexed for it
+/// doesn't match the amount the node has.
+async fn find_mismatched_blocks(
+    database: &tokio_postgres::Client,
+    bitcoin_rpc: &BitcoinRpc,
+    from: i64,
+    concurrency: usize,
+) -> Result<Vec<i64>, ProcessBlockError> {
+    let query = "
+        SELECT blocks.height, blocks.hash, COUNT(transactions.id) AS tx_count
+        FROM blocks
+        LEFT JOIN transactions
+            ON transactions.block_id = blocks.id
+        WHERE blocks.height >= $1
+        GROUP BY blocks.id
+    ";
+
+    let blocks = database.query(query, &[&from]).await?;
+
+    futures::stream::iter(blocks)
+        .map(|row| async move {
+            let height: i64 = row.get("height");
+            let tx_count: i64 = row.get("tx_count");
+            let hash = BlockHash::from_slice(row.get("hash")).unwrap();
+
+            let expected = bitcoin_rpc.get_block_transaction_count(&hash).await?;
+
+            Ok((height, u64::try_from(tx_count).unwrap() == expected))
+        })
+        .buffer_unordered(concurrency)
+        .try_filter_map(|(height, matches)| async move { Ok((!matches).then_some(height)) })
+        .try_collect()
+        .await
+}
+
+/// Records and links up the parent of any blocks indexed before we stored it that couldn't be
+/// taken from the parent they were linked to.
+pub async fn backfill_previous_block_hashes(
+    database: &tokio_postgres::Client,
+    bitcoin_rpc: &BitcoinRpc,
+    concurrency: usize,
+) -> Result<(), ProcessBlockError> {
+    let blocks_query = "
+        SELECT id, hash
+        FROM blocks
+        WHERE previous_block_hash IS NULL
+        AND height > 0
+    ";
+
+    let update_query = "
+        UPDATE blocks
+        SET previous_block_id = (SELECT id FROM blocks WHERE hash = $2),
+            previous_block_hash = $2
+        WHERE id = $1
+    ";
+
+    let blocks = database.query(blocks_query, &[]).await?;
+
+    if blocks.is_empty() {
+        return Ok(());
+    }
+
+    info!(count = blocks.len(), "Backfilling previous block hashes");
+
+    let mut fetched = futures::stream::iter(blocks)
+        .map(|row| async move {
+            let hash = BlockHash::from_slice(row.get("hash")).unwrap();
+            let previous = bitcoin_rpc.get_previous_block_hash(&hash).await?;
+
+            Ok::<_, ProcessBlockError>((row.get::<_, i64>("id"), previous))
+        })
+        .buffer_unordered(concurrency);
+
+    while let Some((block_id, previous)) = fetched.try_next().await? {
+        let previous = previous.map(|hash| hash.as_raw_hash().to_byte_array().to_vec());
+        database
+            .execute(update_query, &[&block_id, &previous])
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Confirms the provisional position given to transactions indexed before we kept track of
+/// it, see the `transaction_positions` migration. This can't be derived from what we've stored,
+/// so the blocks are fetched again from the node.
+pub async fn backfill_positions(
+    database: &mut tokio_postgres::Client,
+    bitcoin_rpc: &BitcoinRpc,
+    concurrency: usize,
+) -> Result<(), ProcessBlockError> {
+    let blocks_query = "
+        SELECT blocks.id, blocks.hash
+        FROM provisional_positions
+        INNER JOIN blocks
+            ON blocks.id = provisional_positions.block_id
+        ORDER BY blocks.height ASC
+    ";
+
+    let update_query = "
+        UPDATE transactions
+        SET position = backfilled.position
+        FROM UNNEST($2::BYTEA[], $3::INT[]) AS backfilled(txid, position)
+        WHERE transactions.block_id = $1
+        AND transactions.txid = backfilled.txid
+    ";
+
+    let address_update_query = "
+        UPDATE address_transactions
+        SET position = transactions.position
+        FROM transactions
+        WHERE transactions.block_id = $1
+        AND address_transactions.transaction_id = transactions.id
+    ";
+
+    let confirmed_query = "DELETE FROM provisional_positions WHERE block_id = $1";
+
+    let blocks = database.query(blocks_query, &[]).await?;
+
+    if blocks.is_empty() {
+        return Ok(());
+    }
+
+    info!(count = blocks.len(), "Backfilling transaction positions");
+
+    let mut fetched = futures::stream::iter(blocks)
+        .map(|row| async move {
+            let hash = BlockHash::from_slice(row.get("hash")).unwrap();
+            let block = bitcoin_rpc.get_block(&hash).await?;
+
+            Ok::<_, ProcessBlockError>((row.get::<_, i64>("id"), block))
+        })
+        .buffered(concurrency);
+
+    while let Some((block_id, block)) = fetched.try_next().await? {
+        let txids: Vec<Vec<u8>> = block
+            .txdata
+            .iter()
+            .map(|transaction| transaction.txid().as_raw_hash().to_byte_array().to_vec())
+            .collect();
+        let positions: Vec<i32> = (0..block.txdata.len() as i32).collect();
+
+        let tx = database.transaction().await?;
+        tx.execute(update_query, &[&block_id, &txids, &positions])
+            .await?;
+        tx.execute(address_update_query, &[&block_id]).await?;
+        tx.execute(confirmed_query, &[&block_id]).await?;
+        tx.commit().await?;
+    }
+
+    info!("Finished backfilling transaction positions");
+
+    Ok(())
+}
@@ -0,0 +1,309 @@
+//! Reads blocks straight out of the node's `blk*.dat` files, which is far quicker than fetching
+//! every block through RPC when indexing the chain from scratch.
+//!
+//! Blocks are written to these files in the order the node downloaded them rather than by
+//! height, and the files may also hold blocks that were since reorganised out of the chain, so
+//! every header is indexed up front and the chain is then pieced together by following each
+//! block's `prev_blockhash` back from the tip.
+
+use bitcoin::{block::Header, consensus::encode::deserialize, Block, BlockHash};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tracing::{info, warn};
+
+#[derive(Error, Debug)]
+pub enum BlockFilesError {
+    #[error("Failed to read block files: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to decode block from block files: {0}")]
+    Decode(#[from] bitcoin::consensus::encode::Error),
+    #[error("Block {0} is missing from the block files, is the node pruned?")]
+    Missing(BlockHash),
+}
+
+/// Where a block can be found within the block files.
+struct Location {
+    file: usize,
+    offset: u64,
+    size: u32,
+    previous: BlockHash,
+}
+
+/// The key the node XORs its block files with, see `-blocksxor`. Files written by nodes that
+/// predate it aren't obfuscated, which is the same as XORing with a key of zeros.
+struct XorKey([u8; 8]);
+
+impl XorKey {
+    fn read(blocks_dir: &Path) -> Result<Self, BlockFilesError> {
+        let mut key = [0; 8];
+
+        match File::open(blocks_dir.join("xor.dat")) {
+            Ok(mut file) => file.read_exact(&mut key)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(Self(key))
+    }
+
+    /// Reverses the obfuscation of `buf`, which was read from `offset` within its file.
+    fn apply(&self, offset: u64, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte ^= self.0[(offset as usize + i) % self.0.len()];
+        }
+    }
+}
+
+/// Reads the blocks from height `from` up to and including `tip` out of the block files in
+/// `blocks_dir`, passing each to `send` in height order until it returns `false`.
+///
+/// Returns the height of the first block that wasn't sent. The block files are only ever a
+/// quicker way of getting hold of blocks the node already has, so if they can't be read, the
+/// node is pruned or it hasn't written out its tip yet, the rest are left to be fetched from
+/// the node as usual.
+pub fn read_blocks(
+    blocks_dir: &Path,
+    from: u64,
+    tip: u64,
+    tip_hash: BlockHash,
+    send: impl FnMut(u64, BlockHash, Block) -> bool,
+) -> u64 {
+    let mut height = from;
+
+    if let Err(e) = try_read_blocks(blocks_dir, &mut height, tip, tip_hash, send) {
+        warn!(%e, height, "Unable to read block from block files, fetching the rest from the node");
+    }
+
+    height
+}
+
+fn try_read_blocks(
+    blocks_dir: &Path,
+    height: &mut u64,
+    tip: u64,
+    tip_hash: BlockHash,
+    mut send: impl FnMut(u64, BlockHash, Block) -> bool,
+) -> Result<(), BlockFilesError> {
+    let key = XorKey::read(blocks_dir)?;
+    let paths = list_block_files(blocks_dir)?;
+
+    info!(files = paths.len(), "Indexing block files");
+    let locations = index_block_files(&paths, &key)?;
+
+    let mut chain = Vec::with_capacity((tip + 1 - *height) as usize);
+    let mut hash = tip_hash;
+
+    for _ in *height..=tip {
+        let location = locations.get(&hash).ok_or(BlockFilesError::Missing(hash))?;
+        chain.push((hash, location));
+        hash = location.previous;
+    }
+
+    chain.reverse();
+
+    info!(from = *height, tip, "Reading blocks from block files");
+
+    let mut files = HashMap::new();
+
+    for (hash, location) in chain {
+        let file = match files.entry(location.file) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(File::open(&paths[location.file])?),
+        };
+
+        let mut bytes = vec![0; location.size as usize];
+        read_at(file, &key, location.offset, &mut bytes)?;
+
+        if !send(*height, hash, deserialize(&bytes)?) {
+            break;
+        }
+
+        *height += 1;
+    }
+
+    Ok(())
+}
+
+/// Finds every `blk?????.dat` file in `blocks_dir`, in the order the node wrote them.
+fn list_block_files(blocks_dir: &Path) -> Result<Vec<PathBuf>, BlockFilesError> {
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(blocks_dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|v| v.to_str())
+            .unwrap_or_default();
+
+        if name.starts_with("blk") && name.ends_with(".dat") {
+            paths.push(path);
+        }
+    }
+
+    paths.sort_unstable();
+
+    Ok(paths)
+}
+
+/// Reads the header of every block in the given files, keyed by the block's hash.
+fn index_block_files(
+    paths: &[PathBuf],
+    key: &XorKey,
+) -> Result<HashMap<BlockHash, Location>, BlockFilesError> {
+    let mut locations = HashMap::new();
+
+    for (file_index, path) in paths.iter().enumerate() {
+        let mut file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut offset = 0;
+
+        // each block is prefixed by the network's magic bytes and the block's length
+        while offset + 8 + 80 <= length {
+            let mut prefix = [0; 8];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut prefix)?;
+
+            // the node allocates space in its files ahead of writing to them, the rest of the
+            // file is zeroed once we've run out of blocks
+            if prefix[..4] == [0; 4] {
+                break;
+            }
+
+            key.apply(offset, &mut prefix);
+            let size = u32::from_le_bytes(prefix[4..].try_into().unwrap());
+
+            // the node may still be part way through writing this one out
+            if offset + 8 + u64::from(size) > length {
+                break;
+            }
+
+            let mut header = [0; 80];
+            read_at(&mut file, key, offset + 8, &mut header)?;
+            let header: Header = deserialize(&header)?;
+
+            locations.insert(
+                header.block_hash(),
+                Location {
+                    file: file_index,
+                    offset: offset + 8,
+                    size,
+                    previous: header.prev_blockhash,
+                },
+            );
+
+            offset += 8 + u64::from(size);
+        }
+    }
+
+    Ok(locations)
+}
+
+fn read_at(file: &mut File, key: &XorKey, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)?;
+    key.apply(offset, buf);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{blockdata::constants::genesis_block, consensus::encode::serialize, Network};
+
+    /// Writes a block file holding `blocks`, each prefixed as the node would and obfuscated
+    /// with `key`, followed by `padding` bytes of zeros.
+    fn write_block_file(name: &str, key: &XorKey, blocks: &[&[u8]], padding: usize) -> PathBuf {
+        let mut bytes = Vec::new();
+
+        for block in blocks {
+            bytes.extend([0xfa, 0xbf, 0xb5, 0xda]);
+            bytes.extend((block.len() as u32).to_le_bytes());
+            bytes.extend(*block);
+        }
+
+        key.apply(0, &mut bytes);
+        bytes.resize(bytes.len() + padding, 0);
+
+        let path = std::env::temp_dir().join(format!("{name}-{}.dat", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn xor_key_is_applied_from_offset() {
+        let key = XorKey([1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut buf = [0; 4];
+        key.apply(6, &mut buf);
+        assert_eq!(buf, [7, 8, 1, 2]);
+
+        // applying it again reverses it
+        key.apply(6, &mut buf);
+        assert_eq!(buf, [0; 4]);
+
+        let mut whole = [0xff; 12];
+        key.apply(0, &mut whole);
+        let mut split = [0xff; 12];
+        key.apply(0, &mut split[..5]);
+        key.apply(5, &mut split[5..]);
+        assert_eq!(whole, split);
+    }
+
+    #[test]
+    fn indexes_blocks_up_to_preallocated_space() {
+        let key = XorKey([0x5a, 0x11, 0, 0xff, 0x80, 0x01, 0x33, 0xc4]);
+        let genesis = genesis_block(Network::Regtest);
+        let mut child = genesis.clone();
+        child.header.prev_blockhash = genesis.block_hash();
+        let (genesis_bytes, child_bytes) = (serialize(&genesis), serialize(&child));
+
+        let path = write_block_file("blk-padded", &key, &[&genesis_bytes, &child_bytes], 4096);
+        let locations = index_block_files(std::slice::from_ref(&path), &key).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(locations.len(), 2);
+
+        let location = &locations[&genesis.block_hash()];
+        assert_eq!(
+            (location.offset, location.size),
+            (8, genesis_bytes.len() as u32)
+        );
+        assert_eq!(location.previous, genesis.header.prev_blockhash);
+
+        let location = &locations[&child.block_hash()];
+        assert_eq!(location.offset, 8 + genesis_bytes.len() as u64 + 8);
+        assert_eq!(location.previous, genesis.block_hash());
+    }
+
+    #[test]
+    fn skips_truncated_last_block() {
+        let key = XorKey([0; 8]);
+        let genesis = genesis_block(Network::Regtest);
+        let mut child = genesis.clone();
+        child.header.prev_blockhash = genesis.block_hash();
+        let (genesis_bytes, child_bytes) = (serialize(&genesis), serialize(&child));
+
+        // the node is part way through writing out the second block
+        let truncated = &child_bytes[..child_bytes.len() - 10];
+        let path = write_block_file("blk-truncated", &key, &[&genesis_bytes, truncated], 0);
+
+        // correct the length prefix to the full block, as the node would have written it
+        let mut bytes = std::fs::read(&path).unwrap();
+        let prefix = 8 + genesis_bytes.len() + 4;
+        bytes[prefix..prefix + 4].copy_from_slice(&(child_bytes.len() as u32).to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let locations = index_block_files(std::slice::from_ref(&path), &key).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(locations.len(), 1);
+        assert!(locations.contains_key(&genesis.block_hash()));
+    }
+}
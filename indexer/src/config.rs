@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::path::PathBuf;
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
@@ -19,8 +20,73 @@ impl Config {
 #[serde(rename_all = "kebab-case")]
 pub struct BitcoinRpc {
     pub address: String,
-    pub username: String,
-    pub password: String,
+    /// Connect to the node over https rather than plain http.
+    #[serde(default)]
+    pub https: bool,
+    /// PEM encoded certificate to trust in addition to the system's roots when connecting over
+    /// https, for nodes behind a proxy with a self-signed certificate.
+    pub ca_certificate: Option<PathBuf>,
+    /// Path to send requests to, defaults to the node's root endpoint which doesn't require a
+    /// wallet to be loaded.
+    #[serde(default)]
+    pub path: String,
+    /// The node's `.cookie` file, which takes precedence over `username` and `password` and
+    /// is read again whenever the node rejects our credentials.
+    pub cookie_file: Option<PathBuf>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Where to fetch blocks from, fetching them through the REST interface requires the node
+    /// to be started with `-rest`.
+    #[serde(default)]
+    pub block_source: BlockSource,
+    /// The node's `blocks` directory, when the node shares a filesystem with us. Blocks up to
+    /// the node's tip at startup are read straight from its `blk*.dat` files rather than being
+    /// fetched from it, which is much quicker for an initial sync.
+    pub blocks_dir: Option<PathBuf>,
+    /// Amount of blocks we need to be behind the node's tip by at startup before reading them
+    /// from `blocks_dir`, every block file has to be scanned first so it's only worth it when
+    /// there's a lot to catch up on.
+    #[serde(default = "BitcoinRpc::default_blocks_dir_threshold")]
+    pub blocks_dir_threshold: u64,
+    /// Seconds to wait for the node to respond to a request before giving up on it.
+    #[serde(default = "BitcoinRpc::default_timeout")]
+    pub timeout: u64,
+    /// Amount of times to retry a request that failed because the node was unreachable,
+    /// overloaded or still starting up.
+    #[serde(default = "BitcoinRpc::default_retries")]
+    pub retries: u32,
+    /// Milliseconds to wait before the first retry, doubling with every attempt after that.
+    #[serde(default = "BitcoinRpc::default_retry_backoff")]
+    pub retry_backoff: u64,
+}
+
+impl BitcoinRpc {
+    const fn default_blocks_dir_threshold() -> u64 {
+        10_000
+    }
+
+    const fn default_timeout() -> u64 {
+        30
+    }
+
+    const fn default_retries() -> u32 {
+        10
+    }
+
+    const fn default_retry_backoff() -> u64 {
+        500
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockSource {
+    /// Hex encoded blocks through `getblock`.
+    #[default]
+    JsonRpc,
+    /// Binary blocks through `/rest/block/<hash>.bin`, falling back to JSON-RPC if the REST
+    /// interface can't be reached.
+    Rest,
 }
 
 #[derive(Deserialize, Debug, Clone)]
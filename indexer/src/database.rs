@@ -1,8 +1,13 @@
 use crate::DatabaseConfig;
+use bitcoin::{
+    absolute::LockTime, hashes::Hash, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness,
+};
 use deadpool_postgres::{Config, CreatePoolError, ManagerConfig, RecyclingMethod, Runtime};
 use refinery::embed_migrations;
-use std::{ops::Deref, sync::Arc};
+use std::{collections::BTreeMap, ops::Deref, sync::Arc};
 use tokio_postgres::NoTls;
+use tracing::info;
 
 embed_migrations!("../migrations");
 
@@ -38,3 +43,194 @@ impl Database {
         )))
     }
 }
+
+/// Fetches the height of the last block connected to the chain, every block up to and including
+/// this height has been indexed.
+pub async fn fetch_indexed_height(
+    db: &tokio_postgres::Client,
+) -> Result<Option<u64>, tokio_postgres::Error> {
+    let row = db.query_one("SELECT height FROM sync_state", &[]).await?;
+    let height: i64 = row.get("height");
+
+    Ok(u64::try_from(height).ok())
+}
+
+/// Hashes are stored in internal byte order, but searched for by a prefix of their displayed
+/// byte-reversed form, so that's stored alongside them.
+pub fn reversed(hash: impl AsRef<[u8]>) -> Vec<u8> {
+    let mut hash = hash.as_ref().to_vec();
+    hash.reverse();
+    hash
+}
+
+/// Stores the reversed hash of any blocks and transactions indexed before we kept it alongside
+/// them, see [`reversed`].
+pub async fn backfill_reversed_hashes(
+    db: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    let blocks_query = "
+        SELECT id, hash
+        FROM blocks
+        WHERE id > $1
+        AND reversed_hash IS NULL
+        ORDER BY id ASC
+        LIMIT 10000
+    ";
+
+    let blocks_update_query = "
+        UPDATE blocks
+        SET reversed_hash = backfilled.reversed_hash
+        FROM UNNEST($1::BIGINT[], $2::BYTEA[]) AS backfilled(id, reversed_hash)
+        WHERE blocks.id = backfilled.id
+    ";
+
+    // transactions we don't know the txid of yet get theirs alongside it, see `backfill_txids`
+    let transactions_query = "
+        SELECT id, txid AS hash
+        FROM transactions
+        WHERE id > $1
+        AND reversed_txid IS NULL
+        AND txid IS NOT NULL
+        ORDER BY id ASC
+        LIMIT 10000
+    ";
+
+    let transactions_update_query = "
+        UPDATE transactions
+        SET reversed_txid = backfilled.reversed_txid
+        FROM UNNEST($1::BIGINT[], $2::BYTEA[]) AS backfilled(id, reversed_txid)
+        WHERE transactions.id = backfilled.id
+    ";
+
+    backfill_reversed(db, blocks_query, blocks_update_query).await?;
+    backfill_reversed(db, transactions_query, transactions_update_query).await
+}
+
+/// Walks every row returned by `select_query` in batches, in order of id, and passes their
+/// reversed `hash` to `update_query`.
+async fn backfill_reversed(
+    db: &tokio_postgres::Client,
+    select_query: &str,
+    update_query: &str,
+) -> Result<(), tokio_postgres::Error> {
+    let mut last_id = 0_i64;
+
+    loop {
+        let rows = db.query(select_query, &[&last_id]).await?;
+
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        info!(count = rows.len(), "Backfilling reversed hashes");
+
+        let ids: Vec<i64> = rows.iter().map(|row| row.get("id")).collect();
+        last_id = ids[ids.len() - 1];
+        let reversed_hashes: Vec<Vec<u8>> = rows
+            .iter()
+            .map(|row| reversed(row.get::<_, &[u8]>("hash")))
+            .collect();
+
+        db.execute(update_query, &[&ids, &reversed_hashes]).await?;
+    }
+}
+
+/// Transactions indexed before we started storing txids separately from wtxids only had their
+/// wtxid stored, so for any of those that have witness data we need to rebuild the transaction
+/// to compute its txid.
+pub async fn backfill_txids(db: &tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
+    let transactions_query = "
+        SELECT id, version, lock_time
+        FROM transactions
+        WHERE txid IS NULL
+        LIMIT 1000
+    ";
+
+    let inputs_query = "
+        SELECT transaction_id, previous_output_transaction, previous_output_index, script, sequence
+        FROM transaction_inputs
+        WHERE transaction_id = ANY($1)
+        ORDER BY transaction_id, index
+    ";
+
+    let outputs_query = "
+        SELECT transaction_id, value, script
+        FROM transaction_outputs
+        WHERE transaction_id = ANY($1)
+        ORDER BY transaction_id, index
+    ";
+
+    let update_query = "
+        UPDATE transactions
+        SET txid = backfilled.txid,
+            reversed_txid = backfilled.reversed_txid
+        FROM UNNEST($1::BIGINT[], $2::BYTEA[], $3::BYTEA[]) AS backfilled(id, txid, reversed_txid)
+        WHERE transactions.id = backfilled.id
+    ";
+
+    loop {
+        let mut transactions: BTreeMap<i64, Transaction> = db
+            .query(transactions_query, &[])
+            .await?
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get("id"),
+                    Transaction {
+                        version: row.get("version"),
+                        lock_time: LockTime::from_consensus(row.get::<_, i32>("lock_time") as u32),
+                        input: Vec::new(),
+                        output: Vec::new(),
+                    },
+                )
+            })
+            .collect();
+
+        if transactions.is_empty() {
+            return Ok(());
+        }
+
+        info!(count = transactions.len(), "Backfilling transaction ids");
+
+        let ids: Vec<i64> = transactions.keys().copied().collect();
+
+        for row in db.query(inputs_query, &[&ids]).await? {
+            let transaction = transactions.get_mut(&row.get("transaction_id")).unwrap();
+
+            transaction.input.push(TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_slice(row.get("previous_output_transaction")).unwrap(),
+                    vout: row.get::<_, i64>("previous_output_index") as u32,
+                },
+                script_sig: ScriptBuf::from_bytes(row.get("script")),
+                sequence: Sequence(row.get::<_, i64>("sequence") as u32),
+                witness: Witness::new(),
+            });
+        }
+
+        for row in db.query(outputs_query, &[&ids]).await? {
+            let transaction = transactions.get_mut(&row.get("transaction_id")).unwrap();
+
+            transaction.output.push(TxOut {
+                value: row.get::<_, i64>("value") as u64,
+                script_pubkey: ScriptBuf::from_bytes(row.get("script")),
+            });
+        }
+
+        let txids: Vec<Vec<u8>> = transactions
+            .values()
+            .map(|transaction| transaction.txid().as_raw_hash().to_byte_array().to_vec())
+            .collect();
+
+        let reversed_txids: Vec<Vec<u8>> = txids.iter().map(reversed).collect();
+
+        db.execute(update_query, &[&ids, &txids, &reversed_txids])
+            .await?;
+
+        // their blocks only count them as segwit once their txid differs from their wtxid
+        crate::update_block_stats(db, &crate::fetch_transaction_blocks(db, &ids).await?).await?;
+
+        // we'll have missed any spends of these transactions whilst we didn't know their txid
+        crate::resolve_spending_inputs(db, &ids).await?;
+    }
+}
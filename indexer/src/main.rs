@@ -1,22 +1,28 @@
 extern crate core;
 
+mod backfill;
+mod blk;
 mod config;
 mod database;
+mod reorg;
 mod rpc;
 
 use crate::{
     config::{Config, DatabaseConfig},
     database::Database,
 };
-use bitcoin::{Address, Block, BlockHash, Network, Transaction, TxIn, TxOut};
+use bitcoin::{hashes::Hash, Address, Block, BlockHash, Network, Transaction, TxIn, TxOut};
 use chrono::{TimeZone, Utc};
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
 use futures::stream::{FuturesOrdered, FuturesUnordered};
 use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::task::JoinHandle;
 use tokio::time::Instant;
-use tracing::{error, Level};
+use tokio_postgres::GenericClient;
+use tracing::{error, info, Level};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -26,89 +32,223 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_max_level(args.logging_level())
         .init();
 
-    let bitcoin_rpc = rpc::BitcoinRpc::new(&args.config.bitcoin_rpc);
+    let bitcoin_rpc = rpc::BitcoinRpc::new(&args.config.bitcoin_rpc)?;
+    let fetch_bitcoin_rpc = bitcoin_rpc.clone();
 
     let database = Database::new(args.config.database)?;
     database::migrations::runner()
         .run_async(&mut **database.get().await?)
         .await?;
 
-    eprintln!(
-        "Current block height: {}",
-        bitcoin_rpc.get_block_height().await
-    );
+    let mut tip = bitcoin_rpc.get_block_height().await?;
+    eprintln!("Current block height: {}", tip);
 
-    let start = args.start;
+    if let Some(Command::Backfill {
+        from,
+        verify_transactions,
+    }) = args.command
+    {
+        backfill::run(
+            database.get().await?.as_mut(),
+            &bitcoin_rpc,
+            from,
+            verify_transactions,
+            args.fetch_concurrent,
+        )
+        .await?;
+
+        return Ok(());
+    }
+
+    // fill in anything indexed before we stored it that can only be recovered from the node,
+    // without holding up the sync
+    let backfill_database = database.clone();
+    let backfill_bitcoin_rpc = bitcoin_rpc.clone();
+    let fetch_concurrent = args.fetch_concurrent;
+    tokio::spawn(async move {
+        let mut database = backfill_database.get().await.unwrap();
+
+        if let Err(e) = reorg::remove_stale_blocks(database.as_mut(), &backfill_bitcoin_rpc).await {
+            error!(?e, "Failed to remove stale blocks");
+        }
+
+        if let Err(e) = database::backfill_reversed_hashes(&database).await {
+            error!(?e, "Failed to backfill reversed hashes");
+        }
+
+        // positions are confirmed by txid, so these need to be known first
+        if let Err(e) = database::backfill_txids(&database).await {
+            error!(?e, "Failed to backfill transaction ids");
+        }
+
+        if let Err(e) = backfill::backfill_previous_block_hashes(
+            &database,
+            &backfill_bitcoin_rpc,
+            fetch_concurrent,
+        )
+        .await
+        {
+            error!(?e, "Failed to backfill previous block hashes");
+        }
+
+        if let Err(e) =
+            backfill::backfill_positions(database.as_mut(), &backfill_bitcoin_rpc, fetch_concurrent)
+                .await
+        {
+            error!(?e, "Failed to backfill transaction positions");
+        }
+    });
+
+    let start = match args.start {
+        Some(start) => start,
+        None => database::fetch_indexed_height(&**database.get().await?)
+            .await?
+            .map_or(0, |height| height + 1),
+    };
+    eprintln!("Starting from block height: {}", start);
 
     let (tx, mut rx) = tokio::sync::mpsc::channel::<(u64, BlockHash, Block)>(args.buffer);
 
     let start_time = Instant::now();
 
-    let fetch_blocks = tokio::spawn(async move {
-        let mut blocks_fetching = FuturesOrdered::new();
+    // reading the block files is only worth it when we're far behind, such as an initial sync
+    let blocks_dir = args
+        .config
+        .bitcoin_rpc
+        .blocks_dir
+        .clone()
+        .filter(|_| tip.saturating_sub(start) >= args.config.bitcoin_rpc.blocks_dir_threshold);
+    let buffer = args.buffer;
+
+    let fetch_blocks: JoinHandle<Result<(), ProcessBlockError>> = tokio::spawn(async move {
+        let mut blocks_fetching: FuturesOrdered<JoinHandle<Result<_, rpc::RpcError>>> =
+            FuturesOrdered::new();
 
         let mut height = start;
+        let mut following = false;
+
+        // read everything the node already has straight from its block files, then fall
+        // through to fetching anything they were missing, or that arrived since, from the node
+        if let Some(blocks_dir) = blocks_dir.filter(|_| height <= tip) {
+            let tip_hash = fetch_bitcoin_rpc.get_block_hash(tip).await?;
+            let tx = tx.clone();
+
+            height = tokio::task::spawn_blocking(move || {
+                blk::read_blocks(&blocks_dir, start, tip, tip_hash, |height, hash, block| {
+                    tx.blocking_send((height, hash, block)).is_ok()
+                })
+            })
+            .await
+            .unwrap();
+        }
 
         loop {
             tokio::select! {
                 Some(task) = blocks_fetching.next() => {
-                    let task: Result<_, _> = task;
-                    tx.send(task.unwrap()).await.unwrap();
+                    for block in task.unwrap()? {
+                        tx.send(block).await.unwrap();
+                    }
+                }
+                // once we've caught up with the node, wait for it to give us some new blocks
+                _ = tokio::time::sleep(Duration::from_secs(args.poll_interval)), if height > tip => {
+                    if !following {
+                        info!(tip, "Caught up with chain tip, waiting for new blocks");
+                        following = true;
+                    }
+
+                    tip = fetch_bitcoin_rpc.get_block_height().await?;
                 }
-                _ = async {}, if blocks_fetching.len() < args.fetch_concurrent => {
-                    let bitcoin_rpc = bitcoin_rpc.clone();
+                _ = async {}, if blocks_fetching.len() < args.fetch_concurrent && height <= tip => {
+                    let bitcoin_rpc = fetch_bitcoin_rpc.clone();
+                    let window = height..=tip.min(height + args.batch_window - 1);
 
-                    if (height % 100) == 0 && (height - start) > 500 && start_time.elapsed().as_secs() > 0 {
+                    if window.clone().any(|height| height % 100 == 0) && (height - start) > 500 && start_time.elapsed().as_secs() > 0 {
                         eprintln!("Average per tx fetched/s: {}. Current {}", (height - start) / start_time.elapsed().as_secs(), height);
                     }
 
+                    height = window.end() + 1;
+
+                    // resolve the whole window to hashes in one request, then fetch their blocks
+                    // in another
                     blocks_fetching.push_back(tokio::spawn(async move {
-                        let hash = bitcoin_rpc.get_block_hash(height).await;
-                        let block = bitcoin_rpc.get_block(&hash).await;
+                        let hashes = bitcoin_rpc.get_block_hashes(window.clone()).await?;
+                        let blocks = bitcoin_rpc.get_blocks(&hashes).await?;
 
-                        (height, hash, block)
+                        Ok(window
+                            .zip(hashes)
+                            .zip(blocks)
+                            .map(|((height, hash), block)| (height, hash, block))
+                            .collect::<Vec<_>>())
                     }));
-
-                    height += 1;
                 }
             }
         }
     });
 
     let process_blocks = tokio::spawn(async move {
-        let mut futures: FuturesUnordered<JoinHandle<_>> = FuturesUnordered::new();
+        let mut futures: FuturesUnordered<JoinHandle<Result<_, ProcessBlockError>>> =
+            FuturesUnordered::new();
         let mut count = 0;
 
+        // blocks are inserted concurrently but have to be connected to their parent in order,
+        // so hold onto any that have been inserted until we've connected everything before them
+        let mut inserted = BTreeMap::new();
+        let mut next_height = start;
+
         loop {
             tokio::select! {
                 Some(task) = futures.next() => {
-                    if let Err(e) = task {
-                        error!(?e, "Failed to insert block");
-                    }
+                    let (height, hash, block) = match task.unwrap() {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!(?e, "Failed to insert block");
+                            return Err(e);
+                        }
+                    };
 
-                    count += 1;
+                    inserted.insert(height, (hash, block));
 
-                    if (count % 100) == 0 && count > 500 && start_time.elapsed().as_secs() > 0 {
-                        eprintln!(
-                            "Average processed/s: {}. Current {}",
-                            count / start_time.elapsed().as_secs(),
-                            count
-                        );
+                    while let Some((hash, block)) = inserted.remove(&next_height) {
+                        let mut database = database.get().await.unwrap();
+
+                        match connect_block(database.as_mut(), next_height as i64, &hash, &block).await {
+                            Err(ProcessBlockError::Reorg(height)) => {
+                                reorg::reindex(database.as_mut(), &bitcoin_rpc, height).await?;
+                            }
+                            res => res?,
+                        }
+
+                        next_height += 1;
+                        count += 1;
+
+                        if (count % 100) == 0 && count > 500 && start_time.elapsed().as_secs() > 0 {
+                            eprintln!(
+                                "Average processed/s: {}. Current {}",
+                                count / start_time.elapsed().as_secs(),
+                                count
+                            );
+                        }
                     }
                 }
-                Some((height, hash, block)) = rx.recv() => {
+                // every block we're holding onto is a full block in memory, so stop taking more
+                // off the channel until some have been connected
+                Some((height, hash, block)) = rx.recv(), if futures.len() + inserted.len() < buffer => {
                     let database = database.clone();
 
                     futures.push(tokio::spawn(async move {
                         let mut database = database.get().await.unwrap();
-                        process_block(database.as_mut(), height as i64, hash, block).await.unwrap();
+                        process_block(database.as_mut(), height as i64, &hash, &block).await?;
+                        Ok((height, hash, block))
                     }));
                 }
             }
         }
     });
 
-    tokio::try_join!(fetch_blocks, process_blocks)?;
+    tokio::select! {
+        res = fetch_blocks => res??,
+        res = process_blocks => res??,
+    }
 
     Ok(())
 }
@@ -117,23 +257,581 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 pub enum ProcessBlockError {
     #[error("Failed to write to database: {0}")]
     Database(#[from] tokio_postgres::Error),
+    #[error("Failed to fetch block from node: {0}")]
+    Rpc(#[from] rpc::RpcError),
+    #[error("Block at height {0} does not build on the indexed chain")]
+    Reorg(i64),
 }
 
+/// Inserts a block and all of its transactions. This can be done concurrently with any other
+/// block, but the block won't be part of the chain until it's passed to [`connect_block`].
 pub async fn process_block(
     database: &mut tokio_postgres::Client,
     height: i64,
-    hash: BlockHash,
-    block: Block,
+    hash: &BlockHash,
+    block: &Block,
 ) -> Result<(), ProcessBlockError> {
     let tx = database.transaction().await?;
 
-    let block_id: i64 = insert_block(&tx, height, &block, &hash).await?;
+    // we might've already inserted this block before being restarted
+    if fetch_block_id(&tx, hash).await?.is_some() {
+        return Ok(());
+    }
 
-    {
-        let tx = &tx;
+    insert_block_data(&tx, height, hash, block).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Connects a block onto its parent, this must be called in height order. Returns
+/// [`ProcessBlockError::Reorg`] if the block doesn't build on the chain we've already indexed.
+///
+/// If the block hasn't been inserted by [`process_block`] yet, it'll be inserted as part of
+/// connecting it.
+pub async fn connect_block(
+    database: &mut tokio_postgres::Client,
+    height: i64,
+    hash: &BlockHash,
+    block: &Block,
+) -> Result<(), ProcessBlockError> {
+    let tx = database.transaction().await?;
+
+    if is_reorg(&tx, height, hash, block).await? {
+        return Err(ProcessBlockError::Reorg(height));
+    }
+
+    let block_id = match fetch_block_id(&tx, hash).await? {
+        Some(id) => id,
+        None => insert_block_data(&tx, height, hash, block).await?,
+    };
+
+    link_previous_block(&tx, block_id, block).await?;
+    resolve_previous_outputs(&tx, block_id).await?;
+
+    let transactions = fetch_block_transactions(&tx, block_id).await?;
+    update_transaction_fees(&tx, &transactions).await?;
+    update_block_stats(&tx, &[block_id]).await?;
+    index_address_transactions(&tx, &transactions).await?;
+
+    tx.execute("UPDATE sync_state SET height = $1", &[&height])
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Reindexes a single block outside of the usual in-order sync, replacing anything we've
+/// already stored at its height.
+pub async fn backfill_block(
+    database: &mut tokio_postgres::Client,
+    height: i64,
+    hash: &BlockHash,
+    block: &Block,
+) -> Result<(), ProcessBlockError> {
+    let tx = database.transaction().await?;
+
+    let existing: Vec<i64> = tx
+        .query("SELECT id FROM blocks WHERE height = $1", &[&height])
+        .await?
+        .into_iter()
+        .map(|row| row.get("id"))
+        .collect();
+
+    delete_blocks(&tx, &existing).await?;
+
+    let block_id = insert_block_data(&tx, height, hash, block).await?;
+
+    link_previous_block(&tx, block_id, block).await?;
+    resolve_previous_outputs(&tx, block_id).await?;
+
+    let transactions = fetch_block_transactions(&tx, block_id).await?;
+    update_transaction_fees(&tx, &transactions).await?;
+    update_block_stats(&tx, &[block_id]).await?;
+    index_address_transactions(&tx, &transactions).await?;
+    resolve_spending_inputs(&tx, &transactions).await?;
+
+    // we may have indexed the next block whilst this one was missing
+    tx.execute(
+        "
+        UPDATE blocks
+        SET previous_block_id = $1
+        WHERE height = $2
+        AND previous_block_id IS NULL
+        AND previous_block_hash = $3
+        ",
+        &[
+            &block_id,
+            &(height + 1),
+            &AsRef::<[u8]>::as_ref(hash.as_raw_hash()),
+        ],
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn link_previous_block(
+    tx: &tokio_postgres::Transaction<'_>,
+    block_id: i64,
+    block: &Block,
+) -> Result<(), tokio_postgres::Error> {
+    tx.execute(
+        "
+        UPDATE blocks
+        SET previous_block_id = (SELECT id FROM blocks WHERE hash = $2),
+            previous_block_hash = $2
+        WHERE id = $1
+        ",
+        &[&block_id, &previous_block_hash(block)],
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The hash of the block's parent as we store it, the genesis block doesn't have one.
+fn previous_block_hash(block: &Block) -> Option<&[u8]> {
+    let hash = &block.header.prev_blockhash;
+
+    (*hash != BlockHash::all_zeros()).then(|| AsRef::<[u8]>::as_ref(hash.as_raw_hash()))
+}
+
+/// Links every input in the given block to the output it spends, copying across the details
+/// of the output and marking it as spent.
+async fn resolve_previous_outputs(
+    tx: &tokio_postgres::Transaction<'_>,
+    block_id: i64,
+) -> Result<(), tokio_postgres::Error> {
+    let link_query = "
+        UPDATE transaction_inputs
+        SET previous_output_id = transaction_outputs.id,
+            previous_output_value = transaction_outputs.value,
+            previous_output_address = transaction_outputs.address
+        FROM transactions, transactions previous_output_transactions
+        INNER JOIN transaction_outputs
+            ON transaction_outputs.transaction_id = previous_output_transactions.id
+        WHERE transactions.block_id = $1
+        AND transaction_inputs.transaction_id = transactions.id
+        AND previous_output_transactions.txid = transaction_inputs.previous_output_transaction
+        AND transaction_outputs.index = transaction_inputs.previous_output_index
+    ";
+
+    let spend_query = "
+        UPDATE transaction_outputs
+        SET spent_by_transaction_id = transaction_inputs.transaction_id,
+            spent_by_input_index = transaction_inputs.index,
+            spent_height = blocks.height
+        FROM transaction_inputs
+        INNER JOIN transactions
+            ON transactions.id = transaction_inputs.transaction_id
+        INNER JOIN blocks
+            ON blocks.id = transactions.block_id
+        WHERE transactions.block_id = $1
+        AND transaction_outputs.id = transaction_inputs.previous_output_id
+    ";
+
+    tx.execute(link_query, &[&block_id]).await?;
+    tx.execute(spend_query, &[&block_id]).await?;
+
+    Ok(())
+}
+
+/// Links any inputs we've already indexed that spend outputs of the given transactions, for
+/// when transactions are indexed after the transactions that spend them.
+async fn resolve_spending_inputs(
+    client: &impl GenericClient,
+    transactions: &[i64],
+) -> Result<(), tokio_postgres::Error> {
+    let link_query = "
+        UPDATE transaction_inputs
+        SET previous_output_id = transaction_outputs.id,
+            previous_output_value = transaction_outputs.value,
+            previous_output_address = transaction_outputs.address
+        FROM transactions previous_output_transactions
+        INNER JOIN transaction_outputs
+            ON transaction_outputs.transaction_id = previous_output_transactions.id
+        WHERE previous_output_transactions.id = ANY($1)
+        AND previous_output_transactions.txid = transaction_inputs.previous_output_transaction
+        AND transaction_outputs.index = transaction_inputs.previous_output_index
+        RETURNING transaction_inputs.transaction_id
+    ";
+
+    let spend_query = "
+        UPDATE transaction_outputs
+        SET spent_by_transaction_id = transaction_inputs.transaction_id,
+            spent_by_input_index = transaction_inputs.index,
+            spent_height = blocks.height
+        FROM transaction_inputs
+        INNER JOIN transactions
+            ON transactions.id = transaction_inputs.transaction_id
+        INNER JOIN blocks
+            ON blocks.id = transactions.block_id
+        WHERE transaction_outputs.transaction_id = ANY($1)
+        AND transaction_outputs.id = transaction_inputs.previous_output_id
+    ";
+
+    let mut spending_transactions: Vec<i64> = client
+        .query(link_query, &[&transactions])
+        .await?
+        .into_iter()
+        .map(|row| row.get("transaction_id"))
+        .collect();
+    spending_transactions.sort_unstable();
+    spending_transactions.dedup();
+
+    client.execute(spend_query, &[&transactions]).await?;
+
+    // the spending transactions now know which addresses they're sending from, and may now
+    // know what they paid in fees
+    update_transaction_fees(client, &spending_transactions).await?;
+    update_block_stats(
+        client,
+        &fetch_transaction_blocks(client, &spending_transactions).await?,
+    )
+    .await?;
+    index_address_transactions(client, &spending_transactions).await?;
+
+    Ok(())
+}
+
+/// Calculates the fee paid by each of the given transactions from the outputs they spend,
+/// leaving it unset for coinbases and for any transaction with inputs we've yet to resolve.
+async fn update_transaction_fees(
+    client: &impl GenericClient,
+    transactions: &[i64],
+) -> Result<(), tokio_postgres::Error> {
+    let query = "
+        UPDATE transactions
+        SET fee = (
+            SELECT SUM(previous_output_value)
+            FROM transaction_inputs
+            WHERE transaction_inputs.transaction_id = transactions.id
+            HAVING COUNT(*) FILTER (WHERE previous_output_id IS NULL) = 0
+        ) - (
+            SELECT COALESCE(SUM(value), 0)
+            FROM transaction_outputs
+            WHERE transaction_outputs.transaction_id = transactions.id
+        )
+        WHERE id = ANY($1)
+        AND NOT coinbase
+    ";
+
+    client.execute(query, &[&transactions]).await?;
+
+    Ok(())
+}
+
+/// Recalculates the statistics of the given blocks from their transactions. Fee statistics
+/// are left unset until every transaction in the block knows its fee.
+async fn update_block_stats(
+    client: &impl GenericClient,
+    blocks: &[i64],
+) -> Result<(), tokio_postgres::Error> {
+    let query = "
+        INSERT INTO block_stats
+        (block_id, weight, tx_count, total_fee, subsidy, min_fee_rate, median_fee_rate, max_fee_rate,
+         fee_rate_percentiles, input_count, output_count, segwit_count, taproot_count)
+        SELECT
+            block_id,
+            weight,
+            tx_count,
+            CASE WHEN fees_known THEN total_fee END,
+            subsidy,
+            CASE WHEN fees_known THEN min_fee_rate END,
+            CASE WHEN fees_known THEN median_fee_rate END,
+            CASE WHEN fees_known THEN max_fee_rate END,
+            CASE WHEN fees_known THEN fee_rate_percentiles END,
+            input_count,
+            output_count,
+            segwit_count,
+            taproot_count
+        FROM (
+            SELECT
+                blocks.id AS block_id,
+                4 * (80 + CASE WHEN COUNT(*) < 253 THEN 1 WHEN COUNT(*) <= 65535 THEN 3 ELSE 5 END)
+                    + SUM(transaction_stats.weight) AS weight,
+                COUNT(*) AS tx_count,
+                BOOL_AND(transaction_stats.coinbase OR transaction_stats.fee IS NOT NULL) AS fees_known,
+                COALESCE(SUM(transaction_stats.fee), 0) AS total_fee,
+                CASE
+                    WHEN blocks.height / 210000 < 64 THEN 5000000000 >> (blocks.height / 210000)::INT
+                    ELSE 0
+                END AS subsidy,
+                MIN(transaction_stats.fee_rate) AS min_fee_rate,
+                PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY transaction_stats.fee_rate) AS median_fee_rate,
+                MAX(transaction_stats.fee_rate) AS max_fee_rate,
+                PERCENTILE_CONT(ARRAY[0.1, 0.25, 0.5, 0.75, 0.9])
+                    WITHIN GROUP (ORDER BY transaction_stats.fee_rate) AS fee_rate_percentiles,
+                COALESCE(SUM(transaction_stats.input_count) FILTER (WHERE NOT transaction_stats.coinbase), 0)
+                    AS input_count,
+                SUM(transaction_stats.output_count) AS output_count,
+                COUNT(*) FILTER (WHERE transaction_stats.segwit AND NOT transaction_stats.coinbase) AS segwit_count,
+                COUNT(*) FILTER (WHERE transaction_stats.taproot) AS taproot_count
+            FROM blocks
+            INNER JOIN (
+                SELECT
+                    transactions.block_id,
+                    transactions.weight,
+                    transactions.coinbase,
+                    transactions.fee,
+                    transactions.fee::DOUBLE PRECISION / transactions.vsize AS fee_rate,
+                    transactions.txid <> transactions.wtxid AS segwit,
+                    inputs.count AS input_count,
+                    inputs.taproot,
+                    outputs.count AS output_count
+                FROM transactions
+                CROSS JOIN LATERAL (
+                    SELECT
+                        COUNT(*) AS count,
+                        COALESCE(BOOL_OR(
+                            LENGTH(previous_outputs.script) = 34
+                            AND SUBSTRING(previous_outputs.script FROM 1 FOR 2) = DECODE('5120', 'hex')
+                        ), false) AS taproot
+                    FROM transaction_inputs
+                    LEFT JOIN transaction_outputs previous_outputs
+                        ON previous_outputs.id = transaction_inputs.previous_output_id
+                    WHERE transaction_inputs.transaction_id = transactions.id
+                ) inputs
+                CROSS JOIN LATERAL (
+                    SELECT COUNT(*) AS count
+                    FROM transaction_outputs
+                    WHERE transaction_outputs.transaction_id = transactions.id
+                ) outputs
+                WHERE transactions.block_id = ANY($1)
+            ) transaction_stats
+                ON transaction_stats.block_id = blocks.id
+            GROUP BY blocks.id
+        ) stats
+        ON CONFLICT (block_id) DO UPDATE
+            SET weight = excluded.weight,
+                tx_count = excluded.tx_count,
+                total_fee = excluded.total_fee,
+                subsidy = excluded.subsidy,
+                min_fee_rate = excluded.min_fee_rate,
+                median_fee_rate = excluded.median_fee_rate,
+                max_fee_rate = excluded.max_fee_rate,
+                fee_rate_percentiles = excluded.fee_rate_percentiles,
+                input_count = excluded.input_count,
+                output_count = excluded.output_count,
+                segwit_count = excluded.segwit_count,
+                taproot_count = excluded.taproot_count
+    ";
+
+    client.execute(query, &[&blocks]).await?;
+
+    Ok(())
+}
+
+/// Fetches the distinct blocks the given transactions belong to.
+async fn fetch_transaction_blocks(
+    client: &impl GenericClient,
+    transactions: &[i64],
+) -> Result<Vec<i64>, tokio_postgres::Error> {
+    Ok(client
+        .query(
+            "SELECT DISTINCT block_id FROM transactions WHERE id = ANY($1)",
+            &[&transactions],
+        )
+        .await?
+        .into_iter()
+        .map(|row| row.get("block_id"))
+        .collect())
+}
+
+/// Rebuilds the per-address history rows for the given transactions from their outputs and
+/// the resolved outputs of their inputs, keeping each address' running totals in step.
+async fn index_address_transactions(
+    client: &impl GenericClient,
+    transactions: &[i64],
+) -> Result<(), tokio_postgres::Error> {
+    let remove_query = "
+        WITH removed AS (
+            DELETE FROM address_transactions
+            WHERE transaction_id = ANY($1)
+            RETURNING *
+        ), totals AS (
+            SELECT
+                address,
+                SUM(received) AS received,
+                SUM(sent) AS sent,
+                COUNT(*) AS transactions,
+                SUM(received_outputs) - SUM(spent_outputs) AS utxos
+            FROM removed
+            GROUP BY address
+        )
+        UPDATE address_summaries
+        SET total_received = address_summaries.total_received - totals.received,
+            total_sent = address_summaries.total_sent - totals.sent,
+            transaction_count = address_summaries.transaction_count - totals.transactions,
+            utxo_count = address_summaries.utxo_count - totals.utxos
+        FROM totals
+        WHERE address_summaries.address = totals.address
+        RETURNING address_summaries.address
+    ";
+
+    let insert_query = "
+        WITH inserted AS (
+            INSERT INTO address_transactions
+            (address, transaction_id, height, position, received, sent, received_outputs, spent_outputs)
+            SELECT
+                movements.address,
+                movements.transaction_id,
+                blocks.height,
+                transactions.position,
+                SUM(movements.received)::BIGINT,
+                SUM(movements.sent)::BIGINT,
+                COUNT(*) FILTER (WHERE movements.is_output),
+                COUNT(*) FILTER (WHERE NOT movements.is_output)
+            FROM (
+                SELECT address, transaction_id, value AS received, 0 AS sent, true AS is_output
+                FROM transaction_outputs
+                WHERE transaction_id = ANY($1)
+                AND address IS NOT NULL
+                UNION ALL
+                SELECT previous_output_address, transaction_id, 0, previous_output_value, false
+                FROM transaction_inputs
+                WHERE transaction_id = ANY($1)
+                AND previous_output_address IS NOT NULL
+            ) movements
+            INNER JOIN transactions
+                ON transactions.id = movements.transaction_id
+            INNER JOIN blocks
+                ON blocks.id = transactions.block_id
+            GROUP BY movements.address, movements.transaction_id, blocks.height, transactions.position
+            RETURNING *
+        )
+        INSERT INTO address_summaries
+        (address, total_received, total_sent, transaction_count, utxo_count, first_seen_height, last_seen_height)
+        SELECT
+            address,
+            SUM(received),
+            SUM(sent),
+            COUNT(*),
+            SUM(received_outputs) - SUM(spent_outputs),
+            MIN(height),
+            MAX(height)
+        FROM inserted
+        GROUP BY address
+        ON CONFLICT (address) DO UPDATE
+            SET total_received = address_summaries.total_received + excluded.total_received,
+                total_sent = address_summaries.total_sent + excluded.total_sent,
+                transaction_count = address_summaries.transaction_count + excluded.transaction_count,
+                utxo_count = address_summaries.utxo_count + excluded.utxo_count
+        RETURNING address
+    ";
+
+    // the seen heights can't be unwound like the totals can, but they're cheap to look up
+    // from the history index
+    let refresh_query = "
+        UPDATE address_summaries
+        SET first_seen_height = (
+                SELECT MIN(height) FROM address_transactions WHERE address = address_summaries.address
+            ),
+            last_seen_height = (
+                SELECT MAX(height) FROM address_transactions WHERE address = address_summaries.address
+            )
+        WHERE address = ANY($1)
+        AND transaction_count > 0
+    ";
+
+    let mut addresses: Vec<String> = client
+        .query(remove_query, &[&transactions])
+        .await?
+        .into_iter()
+        .chain(client.query(insert_query, &[&transactions]).await?)
+        .map(|row| row.get("address"))
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    client.execute(refresh_query, &[&addresses]).await?;
+    client
+        .execute(
+            "DELETE FROM address_summaries WHERE address = ANY($1) AND transaction_count = 0",
+            &[&addresses],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Checks whether the given block conflicts with what we've already indexed, either by
+/// replacing a block we've stored at the same height or by building on a block other than
+/// the one we've stored at `height - 1`.
+async fn is_reorg(
+    tx: &tokio_postgres::Transaction<'_>,
+    height: i64,
+    hash: &BlockHash,
+    block: &Block,
+) -> Result<bool, tokio_postgres::Error> {
+    let query = "
+        SELECT EXISTS (
+            SELECT 1
+            FROM blocks
+            WHERE (height = $1 AND hash <> $2)
+            OR (height = $3 AND hash <> $4)
+        ) AS reorg
+    ";
+
+    Ok(tx
+        .query_one(
+            query,
+            &[
+                &(height - 1),
+                &AsRef::<[u8]>::as_ref(&block.header.prev_blockhash.as_raw_hash()),
+                &height,
+                &AsRef::<[u8]>::as_ref(&hash.as_raw_hash()),
+            ],
+        )
+        .await?
+        .get("reorg"))
+}
+
+async fn fetch_block_transactions(
+    tx: &tokio_postgres::Transaction<'_>,
+    block_id: i64,
+) -> Result<Vec<i64>, tokio_postgres::Error> {
+    Ok(tx
+        .query(
+            "SELECT id FROM transactions WHERE block_id = $1",
+            &[&block_id],
+        )
+        .await?
+        .into_iter()
+        .map(|row| row.get("id"))
+        .collect())
+}
+
+async fn fetch_block_id(
+    tx: &tokio_postgres::Transaction<'_>,
+    hash: &BlockHash,
+) -> Result<Option<i64>, tokio_postgres::Error> {
+    Ok(tx
+        .query_opt(
+            "SELECT id FROM blocks WHERE hash = $1",
+            &[&AsRef::<[u8]>::as_ref(&hash.as_raw_hash())],
+        )
+        .await?
+        .map(|row| row.get("id")))
+}
+
+async fn insert_block_data(
+    tx: &tokio_postgres::Transaction<'_>,
+    height: i64,
+    hash: &BlockHash,
+    block: &Block,
+) -> Result<i64, tokio_postgres::Error> {
+    let block_id: i64 = insert_block(tx, height, block, hash).await?;
 
-        futures::future::try_join_all(block.txdata.into_iter().map(|transaction| async move {
-            let transaction_id = insert_transaction(tx, block_id, &transaction).await?;
+    futures::future::try_join_all(block.txdata.iter().enumerate().map(
+        |(position, transaction)| async move {
+            let transaction_id =
+                insert_transaction(tx, block_id, position as i32, transaction).await?;
 
             futures::future::try_join(
                 futures::future::try_join_all(transaction.input.iter().enumerate().map(
@@ -148,13 +846,14 @@ pub async fn process_block(
                 )),
             )
             .await
-        }))
-        .await?;
-    }
+        },
+    ))
+    .await?;
 
-    tx.commit().await?;
+    // the fee statistics will be filled in once the block is connected and its inputs resolved
+    update_block_stats(tx, &[block_id]).await?;
 
-    Ok(())
+    Ok(block_id)
 }
 
 async fn insert_block(
@@ -166,8 +865,9 @@ async fn insert_block(
     let query = "
         WITH inserted AS (
             INSERT INTO blocks
-            (hash, height, version, size, merkle_root_hash, timestamp, bits, nonce, difficulty)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            (hash, height, version, size, merkle_root_hash, timestamp, bits, nonce, difficulty,
+             previous_block_hash, reversed_hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             ON CONFLICT DO NOTHING
             RETURNING id
         ) SELECT COALESCE(
@@ -176,7 +876,6 @@ async fn insert_block(
         ) AS id
     ";
 
-    // TODO: previous_block_id
     Ok(tx
         .query_one(
             query,
@@ -192,6 +891,8 @@ async fn insert_block(
                 &(block.header.bits.to_consensus() as i32),
                 &(block.header.nonce as i32),
                 &(block.header.difficulty() as i64),
+                &previous_block_hash(block),
+                &database::reversed(block_hash.as_raw_hash()),
             ],
         )
         .await?
@@ -201,14 +902,18 @@ async fn insert_block(
 async fn insert_transaction(
     tx: &tokio_postgres::Transaction<'_>,
     block_id: i64,
+    position: i32,
     transaction: &Transaction,
 ) -> Result<i64, tokio_postgres::Error> {
     let query = "
         INSERT INTO transactions
-        (hash, block_id, version, lock_time, weight, coinbase, replace_by_fee)
-        VALUES ($1, $2, $3, $4, $5, $6, $7)
-        ON CONFLICT (hash) DO UPDATE
-            SET block_id = excluded.block_id
+        (txid, wtxid, block_id, position, version, lock_time, weight, vsize, coinbase, replace_by_fee,
+         reversed_txid)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        ON CONFLICT (txid) DO UPDATE
+            SET block_id = excluded.block_id,
+                position = excluded.position,
+                wtxid = excluded.wtxid
         RETURNING id
     ";
 
@@ -216,13 +921,17 @@ async fn insert_transaction(
         .query_one(
             query,
             &[
+                &AsRef::<[u8]>::as_ref(&transaction.txid().as_raw_hash()),
                 &AsRef::<[u8]>::as_ref(&transaction.wtxid().as_raw_hash()),
                 &block_id,
+                &position,
                 &transaction.version,
                 &(transaction.lock_time.to_consensus_u32() as i32),
                 &(transaction.weight().to_wu() as i64),
+                &(transaction.vsize() as i64),
                 &transaction.is_coin_base(),
                 &transaction.is_explicitly_rbf(),
+                &database::reversed(transaction.txid().as_raw_hash()),
             ],
         )
         .await?
@@ -290,6 +999,128 @@ async fn insert_transaction_output(
     Ok(())
 }
 
+/// Removes the given blocks along with all of their transactions.
+async fn delete_blocks(
+    tx: &tokio_postgres::Transaction<'_>,
+    blocks: &[i64],
+) -> Result<(), tokio_postgres::Error> {
+    tx.execute(
+        "
+        UPDATE transaction_outputs
+        SET spent_by_transaction_id = NULL,
+            spent_by_input_index = NULL,
+            spent_height = NULL
+        FROM transactions
+        WHERE transaction_outputs.spent_by_transaction_id = transactions.id
+        AND transactions.block_id = ANY($1)
+        ",
+        &[&blocks],
+    )
+    .await?;
+
+    // unlink any inputs that aren't being removed from the outputs they spend
+    let unlinked_transactions: Vec<i64> = tx
+        .query(
+            "
+            UPDATE transaction_inputs
+            SET previous_output_id = NULL,
+                previous_output_value = NULL,
+                previous_output_address = NULL
+            FROM transaction_outputs
+            INNER JOIN transactions
+                ON transactions.id = transaction_outputs.transaction_id
+            WHERE transaction_inputs.previous_output_id = transaction_outputs.id
+            AND transactions.block_id = ANY($1)
+            RETURNING transaction_inputs.transaction_id
+            ",
+            &[&blocks],
+        )
+        .await?
+        .into_iter()
+        .map(|row| row.get("transaction_id"))
+        .collect();
+
+    tx.execute(
+        "
+        DELETE FROM transaction_inputs
+        USING transactions
+        WHERE transaction_inputs.transaction_id = transactions.id
+        AND transactions.block_id = ANY($1)
+        ",
+        &[&blocks],
+    )
+    .await?;
+
+    tx.execute(
+        "
+        DELETE FROM transaction_outputs
+        USING transactions
+        WHERE transaction_outputs.transaction_id = transactions.id
+        AND transactions.block_id = ANY($1)
+        ",
+        &[&blocks],
+    )
+    .await?;
+
+    // with their inputs and outputs gone, reindexing the removed transactions unwinds them
+    // from the address history, alongside any surviving transactions that spent from the
+    // removed blocks and no longer know which addresses they were sending from
+    let mut transactions: Vec<i64> = tx
+        .query(
+            "SELECT id FROM transactions WHERE block_id = ANY($1)",
+            &[&blocks],
+        )
+        .await?
+        .into_iter()
+        .map(|row| row.get("id"))
+        .chain(unlinked_transactions)
+        .collect();
+    transactions.sort_unstable();
+    transactions.dedup();
+
+    update_transaction_fees(tx, &transactions).await?;
+    index_address_transactions(tx, &transactions).await?;
+
+    // blocks we're keeping may have just lost the fees of transactions spending from the
+    // removed blocks
+    let affected_blocks: Vec<i64> = fetch_transaction_blocks(tx, &transactions)
+        .await?
+        .into_iter()
+        .filter(|block| !blocks.contains(block))
+        .collect();
+    update_block_stats(tx, &affected_blocks).await?;
+
+    tx.execute(
+        "DELETE FROM block_stats WHERE block_id = ANY($1)",
+        &[&blocks],
+    )
+    .await?;
+
+    tx.execute(
+        "DELETE FROM provisional_positions WHERE block_id = ANY($1)",
+        &[&blocks],
+    )
+    .await?;
+
+    tx.execute(
+        "DELETE FROM transactions WHERE block_id = ANY($1)",
+        &[&blocks],
+    )
+    .await?;
+
+    // unlink any children that aren't being removed alongside their parent
+    tx.execute(
+        "UPDATE blocks SET previous_block_id = NULL WHERE previous_block_id = ANY($1)",
+        &[&blocks],
+    )
+    .await?;
+
+    tx.execute("DELETE FROM blocks WHERE id = ANY($1)", &[&blocks])
+        .await?;
+
+    Ok(())
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
@@ -298,15 +1129,37 @@ pub struct Args {
     pub verbose: u8,
     #[arg(short, long, value_parser = Config::from_toml_path)]
     pub config: Config,
-    /// Block height to start at
-    #[arg(short, long)]
-    pub start: u64,
-    /// Channel buffer between grab & push to db
+    /// Block height to start at, defaults to resuming after the last indexed block
     #[arg(short, long)]
+    pub start: Option<u64>,
+    /// Channel buffer between grab & push to db, also bounding how many blocks are held in memory
+    /// while they wait to be inserted
+    #[arg(short, long, default_value_t = 100)]
     pub buffer: usize,
     /// Amount of concurrent requests to open to bitcoin rpc
     #[arg(short, long)]
     pub fetch_concurrent: usize,
+    /// Amount of heights to fetch from bitcoin rpc in each batched request
+    #[arg(short = 'w', long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub batch_window: u64,
+    /// Seconds to wait between polling for new blocks once we've caught up with the chain tip
+    #[arg(short, long, default_value_t = 5)]
+    pub poll_interval: u64,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Finds any heights missing from the database and reindexes them, rather than syncing
+    Backfill {
+        /// Block height to start searching for gaps from, defaults to the lowest indexed block
+        #[arg(long)]
+        from: Option<u64>,
+        /// Also compare the transaction count of every indexed block against the node
+        #[arg(long)]
+        verify_transactions: bool,
+    },
 }
 
 impl Args {
@@ -0,0 +1,155 @@
+//! Handles the chain tip being replaced by a competing branch, rolling back any blocks we've
+//! indexed that are no longer part of the best chain and indexing the new branch in their
+//! place.
+
+use crate::{connect_block, delete_blocks, rpc::BitcoinRpc, ProcessBlockError};
+use tracing::warn;
+
+/// Rolls back to the point at which our indexed chain forked from the node's best chain and
+/// reindexes every block from there up to and including `height` from the node.
+pub async fn reindex(
+    database: &mut tokio_postgres::Client,
+    bitcoin_rpc: &BitcoinRpc,
+    height: i64,
+) -> Result<(), ProcessBlockError> {
+    'reorg: loop {
+        let fork_height = find_fork_point(database, bitcoin_rpc, height).await?;
+
+        warn!(
+            height,
+            fork_height, "Chain reorganisation detected, rolling back to fork point"
+        );
+
+        rollback(database, fork_height).await?;
+
+        for height in (fork_height + 1)..=height {
+            let hash = bitcoin_rpc.get_block_hash(height as u64).await?;
+            let block = bitcoin_rpc.get_block(&hash).await?;
+
+            match connect_block(database, height, &hash, &block).await {
+                // the chain moved under us again whilst we were reindexing
+                Err(ProcessBlockError::Reorg(_)) => continue 'reorg,
+                res => res?,
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Walks back from `height` until we find the highest block that both we and the node agree
+/// on.
+async fn find_fork_point(
+    database: &tokio_postgres::Client,
+    bitcoin_rpc: &BitcoinRpc,
+    height: i64,
+) -> Result<i64, ProcessBlockError> {
+    let query = "
+        SELECT hash
+        FROM blocks
+        WHERE height = $1
+    ";
+
+    let mut height = height - 1;
+
+    while height >= 0 {
+        let stored = database.query(query, &[&height]).await?;
+        let expected = bitcoin_rpc.get_block_hash(height as u64).await?;
+
+        if stored.iter().all(|row| {
+            row.get::<_, &[u8]>("hash") == AsRef::<[u8]>::as_ref(&expected.as_raw_hash())
+        }) {
+            break;
+        }
+
+        height -= 1;
+    }
+
+    Ok(height)
+}
+
+/// Atomically removes every block above `fork_height` along with their transactions.
+async fn rollback(
+    database: &mut tokio_postgres::Client,
+    fork_height: i64,
+) -> Result<(), tokio_postgres::Error> {
+    let tx = database.transaction().await?;
+
+    let blocks: Vec<i64> = tx
+        .query("SELECT id FROM blocks WHERE height > $1", &[&fork_height])
+        .await?
+        .into_iter()
+        .map(|row| row.get("id"))
+        .collect();
+
+    delete_blocks(&tx, &blocks).await?;
+
+    tx.execute(
+        "UPDATE sync_state SET height = LEAST(height, $1)",
+        &[&fork_height],
+    )
+    .await?;
+
+    tx.commit().await
+}
+
+/// Removes blocks left over from reorgs that happened before we rolled them back, which are
+/// still sat alongside the best chain's block at the same height. The children of the blocks
+/// left behind couldn't be linked to them whilst it was ambiguous which was their parent, see
+/// the `link_previous_blocks` migration, that's left to `backfill_previous_block_hashes`.
+pub async fn remove_stale_blocks(
+    database: &mut tokio_postgres::Client,
+    bitcoin_rpc: &BitcoinRpc,
+) -> Result<(), ProcessBlockError> {
+    let heights_query = "
+        SELECT height
+        FROM blocks
+        GROUP BY height
+        HAVING COUNT(*) > 1
+        ORDER BY height ASC
+    ";
+
+    let stale_query = "
+        SELECT id
+        FROM blocks
+        WHERE height = $1
+        AND hash != $2
+    ";
+
+    let heights: Vec<i64> = database
+        .query(heights_query, &[])
+        .await?
+        .into_iter()
+        .map(|row| row.get("height"))
+        .collect();
+
+    if heights.is_empty() {
+        return Ok(());
+    }
+
+    warn!(
+        count = heights.len(),
+        "Removing blocks that are no longer part of the best chain"
+    );
+
+    for height in heights {
+        let expected = bitcoin_rpc.get_block_hash(height as u64).await?;
+
+        let tx = database.transaction().await?;
+
+        let stale: Vec<i64> = tx
+            .query(
+                stale_query,
+                &[&height, &AsRef::<[u8]>::as_ref(expected.as_raw_hash())],
+            )
+            .await?
+            .into_iter()
+            .map(|row| row.get("id"))
+            .collect();
+
+        delete_blocks(&tx, &stale).await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
@@ -1,114 +1,573 @@
-use std::sync::Arc;
+use std::future::Future;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+};
+use std::time::Duration;
 
+use crate::config::BlockSource;
 use base64::Engine;
-use bitcoin::{Block, BlockHash};
+use bitcoin::{consensus::Decodable, Block, BlockHash};
 use reqwest::{
-    header::{HeaderMap, AUTHORIZATION},
-    Client,
+    header::{HeaderValue, InvalidHeaderValue, AUTHORIZATION},
+    Certificate, Client, StatusCode,
 };
-use serde::Deserialize;
-use serde_json::json;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::warn;
+
+/// bitcoind is still starting up and can't serve requests yet.
+const RPC_IN_WARMUP: i64 = -28;
+
+/// Upper bound on the time we'll wait between retries, however many attempts we've made.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum RpcError {
+    #[error("Failed to send request to node: {0}")]
+    Transport(#[from] reqwest::Error),
+    #[error("Node responded with {0}")]
+    Status(StatusCode),
+    #[error("Node returned error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Node returned neither a result nor an error")]
+    MissingResult,
+    #[error("Node returned {received} responses to a batch of {expected} requests")]
+    BatchSize { expected: usize, received: usize },
+    #[error("Node returned a response to a batch with an unexpected id {0}")]
+    BatchId(u64),
+    #[error("Failed to decode response from node: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to decode hex returned by node: {0}")]
+    Hex(#[from] bitcoin::hashes::hex::Error),
+    #[error("Failed to decode block returned by node: {0}")]
+    Consensus(#[from] bitcoin::consensus::encode::Error),
+    #[error("RPC credentials can't be sent as a header: {0}")]
+    InvalidCredentials(#[from] InvalidHeaderValue),
+    #[error("No RPC credentials configured, either a cookie file or a username and password are required")]
+    MissingCredentials,
+    #[error("Failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to load CA certificate: {0}")]
+    Certificate(reqwest::Error),
+}
+
+impl RpcError {
+    /// Whether the request is worth retrying, ie. the failure was down to the node or the
+    /// network rather than the request itself.
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Transport(e) => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+            Self::Status(status) => status.is_server_error(),
+            Self::Rpc { code, .. } => *code == RPC_IN_WARMUP,
+            // the node removes its cookie file when it shuts down and writes a new one once it's
+            // started back up
+            Self::Read { source, .. } => source.kind() == std::io::ErrorKind::NotFound,
+            Self::MissingResult
+            | Self::BatchSize { .. }
+            | Self::BatchId(_)
+            | Self::Json(_)
+            | Self::Hex(_)
+            | Self::Consensus(_)
+            | Self::InvalidCredentials(_)
+            | Self::MissingCredentials
+            | Self::Certificate(_) => false,
+        }
+    }
+}
+
+/// The `Authorization` header sent with every request. Cookies are rewritten by the node every
+/// time it restarts, so they're only read once we first need them, and again whenever the node
+/// stops accepting the one we have.
+enum Credentials {
+    Static(HeaderValue),
+    Cookie {
+        path: PathBuf,
+        header: RwLock<Option<HeaderValue>>,
+    },
+}
+
+impl Credentials {
+    fn new(config: &crate::config::BitcoinRpc) -> Result<Self, RpcError> {
+        match (&config.cookie_file, &config.username, &config.password) {
+            (Some(path), _, _) => Ok(Self::Cookie {
+                header: RwLock::new(None),
+                path: path.clone(),
+            }),
+            (None, Some(username), Some(password)) => {
+                Ok(Self::Static(basic_auth(&format!("{username}:{password}"))?))
+            }
+            (None, _, _) => Err(RpcError::MissingCredentials),
+        }
+    }
+
+    fn header(&self) -> Result<HeaderValue, RpcError> {
+        match self {
+            Self::Static(header) => Ok(header.clone()),
+            Self::Cookie { path, header } => {
+                if let Some(header) = &*header.read().unwrap() {
+                    return Ok(header.clone());
+                }
+
+                let cookie = read_cookie(path)?;
+                *header.write().unwrap() = Some(cookie.clone());
+
+                Ok(cookie)
+            }
+        }
+    }
+
+    /// Reads the cookie file again, returning whether it's changed since we last read it.
+    fn refresh(&self) -> Result<bool, RpcError> {
+        let Self::Cookie { path, header } = self else {
+            return Ok(false);
+        };
+
+        let cookie = read_cookie(path)?;
+        let mut header = header.write().unwrap();
+
+        if header.as_ref() == Some(&cookie) {
+            return Ok(false);
+        }
+
+        *header = Some(cookie);
+        Ok(true)
+    }
+}
+
+fn read_cookie(path: &Path) -> Result<HeaderValue, RpcError> {
+    let cookie = std::fs::read_to_string(path).map_err(|source| RpcError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    basic_auth(cookie.trim())
+}
+
+fn basic_auth(credentials: &str) -> Result<HeaderValue, RpcError> {
+    let mut header: HeaderValue = format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(credentials)
+    )
+    .parse()?;
+    header.set_sensitive(true);
+
+    Ok(header)
+}
 
 #[derive(Clone)]
 pub struct BitcoinRpc {
     client: Arc<Client>,
+    credentials: Arc<Credentials>,
     url: Arc<str>,
+    rest_url: Arc<str>,
+    /// Whether blocks should be fetched from the REST interface, this is turned off if the node
+    /// refuses our first request to it so we don't keep trying a node that hasn't enabled it.
+    use_rest: Arc<AtomicBool>,
+    /// Whether the REST interface has served us anything yet, after which any failures are
+    /// assumed to be specific to that request.
+    rest_available: Arc<AtomicBool>,
+    retries: u32,
+    retry_backoff: Duration,
 }
 
 impl BitcoinRpc {
-    pub fn new(config: &crate::config::BitcoinRpc) -> Self {
-        let client = Arc::new(
-            reqwest::ClientBuilder::new()
-                .default_headers({
-                    let mut headers = HeaderMap::new();
-                    headers.insert(
-                        AUTHORIZATION,
-                        format!(
-                            "Basic {}",
-                            base64::engine::general_purpose::STANDARD
-                                .encode(format!("{}:{}", config.username, config.password))
-                        )
-                        .parse()
-                        .unwrap(),
-                    );
-                    headers
-                })
-                .build()
-                .unwrap(),
-        );
+    pub fn new(config: &crate::config::BitcoinRpc) -> Result<Self, RpcError> {
+        let mut client = reqwest::ClientBuilder::new().timeout(Duration::from_secs(config.timeout));
 
-        Self {
-            client,
-            url: Arc::from(format!("http://{}", config.address)),
+        if let Some(path) = &config.ca_certificate {
+            let pem = std::fs::read(path).map_err(|source| RpcError::Read {
+                path: path.clone(),
+                source,
+            })?;
+
+            client = client
+                .add_root_certificate(Certificate::from_pem(&pem).map_err(RpcError::Certificate)?);
         }
+
+        let scheme = if config.https { "https" } else { "http" };
+        let path = config.path.trim_start_matches('/');
+
+        Ok(Self {
+            client: Arc::new(client.build()?),
+            credentials: Arc::new(Credentials::new(config)?),
+            url: Arc::from(format!("{scheme}://{}/{path}", config.address)),
+            rest_url: Arc::from(format!("{scheme}://{}/rest", config.address)),
+            use_rest: Arc::new(AtomicBool::new(config.block_source == BlockSource::Rest)),
+            rest_available: Arc::new(AtomicBool::new(false)),
+            retries: config.retries,
+            retry_backoff: Duration::from_millis(config.retry_backoff),
+        })
     }
 
-    pub async fn get_block_height(&self) -> u64 {
-        self.client
-            .post(&*self.url)
-            .json(&json!({
-                "jsonrpc": "1.0",
-                "id": 0,
-                "method": "getblockcount",
-                "params": []
-            }))
-            .send()
-            .await
-            .unwrap()
-            .json::<RpcResult<u64>>()
-            .await
-            .unwrap()
-            .result
+    pub async fn get_block_height(&self) -> Result<u64, RpcError> {
+        self.call("getblockcount", json!([])).await
     }
 
-    pub async fn get_block_hash(&self, height: u64) -> BlockHash {
-        self.client
-            .post(&*self.url)
-            .json(&json!({
-                "jsonrpc": "1.0",
-                "id": 0,
-                "method": "getblockhash",
-                "params": [height],
-            }))
-            .send()
-            .await
-            .unwrap()
-            .json::<RpcResult<BlockHash>>()
-            .await
-            .unwrap()
-            .result
+    pub async fn get_block_hash(&self, height: u64) -> Result<BlockHash, RpcError> {
+        if self.use_rest.load(Ordering::Relaxed) {
+            match self.rest(format!("blockhashbyheight/{height}.bin")).await {
+                Ok(hash) => return Ok(hash),
+                Err(e) => self.rest_failed(&e),
+            }
+        }
+
+        self.call("getblockhash", json!([height])).await
     }
 
-    pub async fn get_block(&self, hash: &BlockHash) -> Block {
+    pub async fn get_block_transaction_count(&self, hash: &BlockHash) -> Result<u64, RpcError> {
         let hash = hash.to_string();
 
-        let res = self
+        Ok(self
+            .call::<BlockHeaderInfo>("getblockheader", json!([hash, true]))
+            .await?
+            .transaction_count)
+    }
+
+    /// Fetches the hash of the block's parent, which the genesis block doesn't have.
+    pub async fn get_previous_block_hash(
+        &self,
+        hash: &BlockHash,
+    ) -> Result<Option<BlockHash>, RpcError> {
+        let hash = hash.to_string();
+
+        Ok(self
+            .call::<BlockHeaderInfo>("getblockheader", json!([hash, true]))
+            .await?
+            .previous_block_hash)
+    }
+
+    pub async fn get_block(&self, hash: &BlockHash) -> Result<Block, RpcError> {
+        if self.use_rest.load(Ordering::Relaxed) {
+            match self.rest(format!("block/{hash}.bin")).await {
+                Ok(block) => return Ok(block),
+                Err(e) => self.rest_failed(&e),
+            }
+        }
+
+        let hash = hash.to_string();
+
+        decode_block(&self.call::<String>("getblock", json!([hash, 0])).await?)
+    }
+
+    /// Resolves every height in `heights` to the hash of the block at that height in a single
+    /// batched request, or concurrently if we're using the REST interface.
+    pub async fn get_block_hashes(
+        &self,
+        heights: RangeInclusive<u64>,
+    ) -> Result<Vec<BlockHash>, RpcError> {
+        if self.use_rest.load(Ordering::Relaxed) {
+            let requests = heights
+                .clone()
+                .map(|height| self.rest(format!("blockhashbyheight/{height}.bin")));
+
+            match futures::future::try_join_all(requests).await {
+                Ok(hashes) => return Ok(hashes),
+                Err(e) => self.rest_failed(&e),
+            }
+        }
+
+        self.call_batch(
+            "getblockhash",
+            heights.map(|height| json!([height])).collect(),
+        )
+        .await
+    }
+
+    /// Fetches every block in `hashes` in a single batched request, or concurrently if we're
+    /// using the REST interface, in the order they were given.
+    pub async fn get_blocks(&self, hashes: &[BlockHash]) -> Result<Vec<Block>, RpcError> {
+        if self.use_rest.load(Ordering::Relaxed) {
+            let requests = hashes
+                .iter()
+                .map(|hash| self.rest(format!("block/{hash}.bin")));
+
+            match futures::future::try_join_all(requests).await {
+                Ok(blocks) => return Ok(blocks),
+                Err(e) => self.rest_failed(&e),
+            }
+        }
+
+        let params = hashes
+            .iter()
+            .map(|hash| json!([hash.to_string(), 0]))
+            .collect();
+
+        self.call_batch::<String>("getblock", params)
+            .await?
+            .iter()
+            .map(|res| decode_block(res))
+            .collect()
+    }
+
+    /// Calls `method` on the node, retrying with an exponential backoff for as long as it's
+    /// failing for reasons that might resolve themselves.
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, RpcError> {
+        let request = json!({
+            "jsonrpc": "1.0",
+            "id": 0,
+            "method": method,
+            "params": params,
+        });
+
+        self.with_retries(method, || async {
+            self.post::<RpcResult<T>>(&request).await?.into_result()
+        })
+        .await
+    }
+
+    /// Calls `method` once for each of the given sets of parameters as a single JSON-RPC batch,
+    /// returning the results in the same order. The whole batch is retried if any one of the
+    /// calls fails for a reason that might resolve itself.
+    async fn call_batch<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> Result<Vec<T>, RpcError> {
+        if params.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let request = Value::Array(
+            params
+                .iter()
+                .enumerate()
+                .map(|(id, params)| {
+                    json!({
+                        "jsonrpc": "1.0",
+                        "id": id,
+                        "method": method,
+                        "params": params,
+                    })
+                })
+                .collect(),
+        );
+
+        self.with_retries(method, || async {
+            let responses = self.post::<Vec<RpcResult<T>>>(&request).await?;
+
+            order_batch(responses, params.len())
+        })
+        .await
+    }
+
+    /// Fetches and decodes a binary resource from the node's REST interface, retrying in the
+    /// same way as JSON-RPC calls.
+    async fn rest<T: Decodable>(&self, resource: String) -> Result<T, RpcError> {
+        let url = format!("{}/{resource}", self.rest_url);
+
+        self.with_retries("rest", || async {
+            let res = self.client.get(&url).send().await?;
+
+            if !res.status().is_success() {
+                return Err(RpcError::Status(res.status()));
+            }
+
+            let decoded = bitcoin::consensus::encode::deserialize(&res.bytes().await?)?;
+            self.rest_available.store(true, Ordering::Relaxed);
+
+            Ok(decoded)
+        })
+        .await
+    }
+
+    /// Falls back to JSON-RPC for a request the REST interface couldn't serve, giving up on
+    /// REST altogether if it's refused before it's served anything.
+    fn rest_failed(&self, error: &RpcError) {
+        let refused = matches!(
+            error,
+            RpcError::Status(StatusCode::NOT_FOUND | StatusCode::FORBIDDEN)
+        );
+
+        if refused && !self.rest_available.load(Ordering::Relaxed) {
+            if self.use_rest.swap(false, Ordering::Relaxed) {
+                warn!(%error, "REST interface is unavailable, falling back to JSON-RPC");
+            }
+        } else {
+            warn!(%error, "Failed to fetch from REST interface, falling back to JSON-RPC for this request");
+        }
+    }
+
+    async fn with_retries<T, F, Fut>(&self, method: &str, mut f: F) -> Result<T, RpcError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, RpcError>>,
+    {
+        let mut backoff = self.retry_backoff;
+        let mut attempt = 0;
+
+        loop {
+            match f().await {
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    warn!(method, attempt, ?backoff, error = %e, "RPC request failed, retrying");
+
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn post<R: DeserializeOwned>(&self, request: &Value) -> Result<R, RpcError> {
+        let mut res = self.send(request).await?;
+
+        // the node may have restarted and written itself a new cookie since we last read it
+        if res.status() == StatusCode::UNAUTHORIZED && self.credentials.refresh()? {
+            res = self.send(request).await?;
+        }
+
+        let status = res.status();
+        let body = res.bytes().await?;
+
+        // bitcoind sends JSON-RPC errors with a non-2xx status, so we'll only report the status
+        // if the body doesn't tell us any more than that
+        match serde_json::from_slice(&body) {
+            Ok(res) => Ok(res),
+            Err(_) if !status.is_success() => Err(RpcError::Status(status)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn send(&self, request: &Value) -> Result<reqwest::Response, RpcError> {
+        Ok(self
             .client
             .post(&*self.url)
-            .json(&json!({
-                "jsonrpc": "1.0",
-                "id": 0,
-                "method": "getblock",
-                "params": [hash, 0],
-            }))
+            .header(AUTHORIZATION, self.credentials.header()?)
+            .json(request)
             .send()
-            .await
-            .unwrap()
-            .json::<RpcResult<String>>()
-            .await
-            .unwrap()
-            .result;
+            .await?)
+    }
+}
+
+fn decode_block(hex: &str) -> Result<Block, RpcError> {
+    let bytes: Vec<u8> = bitcoin::hashes::hex::FromHex::from_hex(hex)?;
 
-        let bytes: Vec<u8> = bitcoin::hashes::hex::FromHex::from_hex(&res).unwrap();
+    Ok(bitcoin::consensus::encode::deserialize(&bytes)?)
+}
 
-        bitcoin::consensus::encode::deserialize(&bytes).unwrap()
+/// Puts the responses to a batch of `expected` requests, with ids counting up from zero, back
+/// into the order the requests were sent in.
+fn order_batch<T>(mut responses: Vec<RpcResult<T>>, expected: usize) -> Result<Vec<T>, RpcError> {
+    if responses.len() != expected {
+        return Err(RpcError::BatchSize {
+            expected,
+            received: responses.len(),
+        });
     }
+
+    // responses to a batch aren't guaranteed to come back in the order they were sent
+    responses.sort_unstable_by_key(|res| res.id);
+
+    if let Some((_, res)) = responses
+        .iter()
+        .enumerate()
+        .find(|(i, res)| res.id != *i as u64)
+    {
+        return Err(RpcError::BatchId(res.id));
+    }
+
+    responses.into_iter().map(RpcResult::into_result).collect()
 }
 
 #[derive(Deserialize)]
-#[allow(dead_code)]
 pub struct RpcResult<T> {
-    result: T,
-    error: Option<String>,
+    result: Option<T>,
+    error: Option<RpcResultError>,
     id: u64,
 }
+
+impl<T> RpcResult<T> {
+    fn into_result(self) -> Result<T, RpcError> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(RpcError::Rpc {
+                code: error.code,
+                message: error.message,
+            }),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(RpcError::MissingResult),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RpcResultError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+pub struct BlockHeaderInfo {
+    #[serde(rename = "nTx")]
+    transaction_count: u64,
+    #[serde(rename = "previousblockhash")]
+    previous_block_hash: Option<BlockHash>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn responses(json: Value) -> Vec<RpcResult<String>> {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn orders_batch_responses_by_id() {
+        let batch = responses(json!([
+            { "result": "c", "error": null, "id": 2 },
+            { "result": "a", "error": null, "id": 0 },
+            { "result": "b", "error": null, "id": 1 },
+        ]));
+
+        assert_eq!(order_batch(batch, 3).unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn rejects_batch_missing_responses() {
+        let batch = responses(json!([
+            { "result": "b", "error": null, "id": 1 },
+            { "result": "a", "error": null, "id": 0 },
+        ]));
+
+        assert!(matches!(
+            order_batch(batch, 3),
+            Err(RpcError::BatchSize {
+                expected: 3,
+                received: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_batch_with_unexpected_ids() {
+        // the right amount of responses, but one of the requests was answered twice
+        let batch = responses(json!([
+            { "result": "c", "error": null, "id": 2 },
+            { "result": "a", "error": null, "id": 0 },
+            { "result": "a", "error": null, "id": 0 },
+        ]));
+
+        assert!(matches!(order_batch(batch, 3), Err(RpcError::BatchId(0))));
+    }
+
+    #[test]
+    fn fails_batch_on_any_error() {
+        let batch = responses(json!([
+            { "result": "b", "error": null, "id": 1 },
+            { "result": null, "error": { "code": -5, "message": "Block not found" }, "id": 0 },
+        ]));
+
+        assert!(matches!(
+            order_batch(batch, 2),
+            Err(RpcError::Rpc { code: -5, .. })
+        ));
+    }
+}
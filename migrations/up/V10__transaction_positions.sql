@@ -0,0 +1,37 @@
+-- transactions indexed before we kept track of their position within their block are given a
+-- provisional one from the order they were inserted in, which matches the block's order for
+-- everything the indexer has written so far. The indexer confirms these against the node in
+-- the background, removing each block from here once it has
+ALTER TABLE transactions ADD COLUMN position INT;
+ALTER TABLE address_transactions ADD COLUMN position INT;
+
+CREATE TABLE provisional_positions (
+    block_id BIGINT PRIMARY KEY,
+    CONSTRAINT fk_block_id
+        FOREIGN KEY(block_id)
+            REFERENCES blocks(id)
+);
+
+-- a block with only its coinbase has nothing to confirm
+INSERT INTO provisional_positions (block_id)
+SELECT block_id
+FROM transactions
+WHERE block_id IS NOT NULL
+GROUP BY block_id
+HAVING COUNT(*) > 1;
+
+UPDATE transactions
+SET position = ranked.position
+FROM (
+    SELECT id, (ROW_NUMBER() OVER (PARTITION BY block_id ORDER BY id) - 1)::INT AS position
+    FROM transactions
+) ranked
+WHERE transactions.id = ranked.id;
+
+UPDATE address_transactions
+SET position = transactions.position
+FROM transactions
+WHERE transactions.id = address_transactions.transaction_id;
+
+DROP INDEX address_transactions_history;
+CREATE INDEX address_transactions_history ON address_transactions (address, height DESC, position DESC);
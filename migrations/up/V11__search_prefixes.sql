@@ -0,0 +1,9 @@
+-- hashes are stored in internal byte order but searched for by their displayed (reversed)
+-- prefix, so the indexer stores them reversed alongside for autocomplete. Anything indexed
+-- before now is filled in by the indexer on startup
+ALTER TABLE blocks ADD COLUMN reversed_hash BYTEA;
+ALTER TABLE transactions ADD COLUMN reversed_txid BYTEA;
+
+CREATE INDEX block_hash_reversed ON blocks (reversed_hash);
+CREATE INDEX transactions_txid_reversed ON transactions (reversed_txid);
+CREATE INDEX address_summaries_address_prefix ON address_summaries (address text_pattern_ops);
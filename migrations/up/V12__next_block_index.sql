@@ -0,0 +1 @@
+CREATE INDEX blocks_previous_block_id ON blocks (previous_block_id);
@@ -0,0 +1,3 @@
+-- lets a block's transactions be paged through in the order they appear in the block
+DROP INDEX transactions_block_id;
+CREATE INDEX transactions_block_position ON transactions (block_id, position);
@@ -0,0 +1,29 @@
+ALTER TABLE transactions
+    ADD COLUMN vsize BIGINT,
+    ADD COLUMN fee BIGINT;
+
+UPDATE transactions SET vsize = (weight + 3) / 4;
+
+ALTER TABLE transactions ALTER COLUMN vsize SET NOT NULL;
+
+-- a fee can only be known once every input has been linked to the output it spends, the
+-- indexer fills in the rest as it resolves them
+UPDATE transactions
+SET fee = inputs.value - COALESCE(outputs.value, 0)
+FROM (
+    SELECT
+        transaction_id,
+        SUM(previous_output_value) AS value,
+        COUNT(*) FILTER (WHERE previous_output_id IS NULL) AS unresolved
+    FROM transaction_inputs
+    GROUP BY transaction_id
+) inputs
+LEFT JOIN (
+    SELECT transaction_id, SUM(value) AS value
+    FROM transaction_outputs
+    GROUP BY transaction_id
+) outputs
+    ON outputs.transaction_id = inputs.transaction_id
+WHERE inputs.transaction_id = transactions.id
+AND inputs.unresolved = 0
+AND NOT transactions.coinbase;
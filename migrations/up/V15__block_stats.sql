@@ -0,0 +1,93 @@
+CREATE TABLE block_stats (
+    block_id BIGINT PRIMARY KEY,
+    weight BIGINT NOT NULL,
+    tx_count BIGINT NOT NULL,
+    total_fee BIGINT,
+    subsidy BIGINT NOT NULL,
+    min_fee_rate DOUBLE PRECISION,
+    median_fee_rate DOUBLE PRECISION,
+    max_fee_rate DOUBLE PRECISION,
+    fee_rate_percentiles DOUBLE PRECISION[],
+    input_count BIGINT NOT NULL,
+    output_count BIGINT NOT NULL,
+    segwit_count BIGINT NOT NULL,
+    taproot_count BIGINT NOT NULL,
+    CONSTRAINT fk_block_id
+        FOREIGN KEY(block_id)
+            REFERENCES blocks(id)
+);
+
+-- kept in step with the indexer's `update_block_stats`
+INSERT INTO block_stats
+(block_id, weight, tx_count, total_fee, subsidy, min_fee_rate, median_fee_rate, max_fee_rate,
+ fee_rate_percentiles, input_count, output_count, segwit_count, taproot_count)
+SELECT
+    block_id,
+    weight,
+    tx_count,
+    CASE WHEN fees_known THEN total_fee END,
+    subsidy,
+    CASE WHEN fees_known THEN min_fee_rate END,
+    CASE WHEN fees_known THEN median_fee_rate END,
+    CASE WHEN fees_known THEN max_fee_rate END,
+    CASE WHEN fees_known THEN fee_rate_percentiles END,
+    input_count,
+    output_count,
+    segwit_count,
+    taproot_count
+FROM (
+    SELECT
+        blocks.id AS block_id,
+        4 * (80 + CASE WHEN COUNT(*) < 253 THEN 1 WHEN COUNT(*) <= 65535 THEN 3 ELSE 5 END)
+            + SUM(transaction_stats.weight) AS weight,
+        COUNT(*) AS tx_count,
+        BOOL_AND(transaction_stats.coinbase OR transaction_stats.fee IS NOT NULL) AS fees_known,
+        COALESCE(SUM(transaction_stats.fee), 0) AS total_fee,
+        CASE
+            WHEN blocks.height / 210000 < 64 THEN 5000000000 >> (blocks.height / 210000)::INT
+            ELSE 0
+        END AS subsidy,
+        MIN(transaction_stats.fee_rate) AS min_fee_rate,
+        PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY transaction_stats.fee_rate) AS median_fee_rate,
+        MAX(transaction_stats.fee_rate) AS max_fee_rate,
+        PERCENTILE_CONT(ARRAY[0.1, 0.25, 0.5, 0.75, 0.9])
+            WITHIN GROUP (ORDER BY transaction_stats.fee_rate) AS fee_rate_percentiles,
+        COALESCE(SUM(transaction_stats.input_count) FILTER (WHERE NOT transaction_stats.coinbase), 0)
+            AS input_count,
+        SUM(transaction_stats.output_count) AS output_count,
+        COUNT(*) FILTER (WHERE transaction_stats.segwit AND NOT transaction_stats.coinbase) AS segwit_count,
+        COUNT(*) FILTER (WHERE transaction_stats.taproot) AS taproot_count
+    FROM blocks
+    INNER JOIN (
+        SELECT
+            transactions.block_id,
+            transactions.weight,
+            transactions.coinbase,
+            transactions.fee,
+            transactions.fee::DOUBLE PRECISION / transactions.vsize AS fee_rate,
+            transactions.txid <> transactions.wtxid AS segwit,
+            inputs.count AS input_count,
+            inputs.taproot,
+            outputs.count AS output_count
+        FROM transactions
+        CROSS JOIN LATERAL (
+            SELECT
+                COUNT(*) AS count,
+                COALESCE(BOOL_OR(
+                    LENGTH(previous_outputs.script) = 34
+                    AND SUBSTRING(previous_outputs.script FROM 1 FOR 2) = DECODE('5120', 'hex')
+                ), false) AS taproot
+            FROM transaction_inputs
+            LEFT JOIN transaction_outputs previous_outputs
+                ON previous_outputs.id = transaction_inputs.previous_output_id
+            WHERE transaction_inputs.transaction_id = transactions.id
+        ) inputs
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) AS count
+            FROM transaction_outputs
+            WHERE transaction_outputs.transaction_id = transactions.id
+        ) outputs
+    ) transaction_stats
+        ON transaction_stats.block_id = blocks.id
+    GROUP BY blocks.id
+) stats;
@@ -0,0 +1,10 @@
+-- keeps hold of each block's parent hash from its header so it doesn't depend on the parent
+-- having been indexed, or linked up to it. Anything that can't be taken from its linked
+-- parent here is filled in from the node by the indexer, apart from the genesis block which
+-- doesn't have a parent
+ALTER TABLE blocks ADD COLUMN previous_block_hash BYTEA;
+
+UPDATE blocks
+SET previous_block_hash = parent.hash
+FROM blocks parent
+WHERE parent.id = blocks.previous_block_id;
@@ -0,0 +1,7 @@
+-- link up any existing blocks we can do so unambiguously, anything else will
+-- be relinked as it's reindexed
+UPDATE blocks
+SET previous_block_id = parent.id
+FROM blocks parent
+WHERE parent.height = blocks.height - 1
+AND (SELECT COUNT(*) FROM blocks b WHERE b.height = parent.height) = 1;
@@ -0,0 +1,15 @@
+CREATE TABLE sync_state (
+    id BOOLEAN PRIMARY KEY DEFAULT true,
+    height BIGINT NOT NULL,
+    CONSTRAINT single_row CHECK (id)
+);
+
+-- resume existing databases from the end of the first contiguous run of blocks
+INSERT INTO sync_state (height)
+SELECT COALESCE(MIN(height), -1)
+FROM blocks
+WHERE NOT EXISTS (
+    SELECT 1
+    FROM blocks successor
+    WHERE successor.height = blocks.height + 1
+);
@@ -0,0 +1,18 @@
+-- we were previously storing the wtxid as the hash of the transaction
+ALTER TABLE transactions RENAME COLUMN hash TO wtxid;
+ALTER INDEX transactions_hash_index RENAME TO transactions_wtxid_index;
+
+ALTER TABLE transactions ADD COLUMN txid BYTEA;
+
+-- transactions without any witness data have the same txid and wtxid, everything else is
+-- backfilled by the indexer on startup
+UPDATE transactions
+SET txid = wtxid
+WHERE NOT EXISTS (
+    SELECT 1
+    FROM transaction_inputs
+    WHERE transaction_inputs.transaction_id = transactions.id
+    AND CARDINALITY(transaction_inputs.witness) > 0
+);
+
+CREATE UNIQUE INDEX transactions_txid_index ON transactions (txid);
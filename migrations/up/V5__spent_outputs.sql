@@ -0,0 +1,24 @@
+ALTER TABLE transaction_outputs
+    ADD COLUMN spent_by_transaction_id BIGINT,
+    ADD COLUMN spent_by_input_index BIGINT,
+    ADD COLUMN spent_height BIGINT,
+    ADD CONSTRAINT fk_spent_by_transaction_id
+        FOREIGN KEY(spent_by_transaction_id)
+            REFERENCES transactions(id);
+
+CREATE INDEX transaction_outputs_spent_by ON transaction_outputs (spent_by_transaction_id);
+CREATE INDEX transaction_inputs_previous_output ON transaction_inputs (previous_output_transaction, previous_output_index);
+
+UPDATE transaction_outputs
+SET spent_by_transaction_id = transaction_inputs.transaction_id,
+    spent_by_input_index = transaction_inputs.index,
+    spent_height = blocks.height
+FROM transaction_inputs
+INNER JOIN transactions
+    ON transactions.id = transaction_inputs.transaction_id
+INNER JOIN blocks
+    ON blocks.id = transactions.block_id
+INNER JOIN transactions previous_output_transactions
+    ON previous_output_transactions.txid = transaction_inputs.previous_output_transaction
+WHERE transaction_outputs.transaction_id = previous_output_transactions.id
+AND transaction_outputs.index = transaction_inputs.previous_output_index;
@@ -0,0 +1,20 @@
+ALTER TABLE transaction_inputs
+    ADD COLUMN previous_output_id BIGINT,
+    ADD COLUMN previous_output_value BIGINT,
+    ADD COLUMN previous_output_address VARCHAR,
+    ADD CONSTRAINT fk_previous_output_id
+        FOREIGN KEY(previous_output_id)
+            REFERENCES transaction_outputs(id);
+
+UPDATE transaction_inputs
+SET previous_output_id = transaction_outputs.id,
+    previous_output_value = transaction_outputs.value,
+    previous_output_address = transaction_outputs.address
+FROM transactions previous_output_transactions
+INNER JOIN transaction_outputs
+    ON transaction_outputs.transaction_id = previous_output_transactions.id
+WHERE previous_output_transactions.txid = transaction_inputs.previous_output_transaction
+AND transaction_outputs.index = transaction_inputs.previous_output_index;
+
+CREATE INDEX transaction_inputs_previous_output_id ON transaction_inputs (previous_output_id);
+CREATE INDEX transaction_inputs_previous_output_address ON transaction_inputs (previous_output_address);
@@ -0,0 +1,31 @@
+CREATE TABLE address_transactions (
+    address VARCHAR NOT NULL,
+    transaction_id BIGINT NOT NULL,
+    height BIGINT NOT NULL,
+    received BIGINT NOT NULL,
+    sent BIGINT NOT NULL,
+    PRIMARY KEY (address, transaction_id),
+    CONSTRAINT fk_transaction_id
+        FOREIGN KEY(transaction_id)
+            REFERENCES transactions(id)
+);
+
+INSERT INTO address_transactions (address, transaction_id, height, received, sent)
+SELECT movements.address, movements.transaction_id, blocks.height, SUM(movements.received), SUM(movements.sent)
+FROM (
+    SELECT address, transaction_id, value AS received, 0 AS sent
+    FROM transaction_outputs
+    WHERE address IS NOT NULL
+    UNION ALL
+    SELECT previous_output_address, transaction_id, 0, previous_output_value
+    FROM transaction_inputs
+    WHERE previous_output_address IS NOT NULL
+) movements
+INNER JOIN transactions
+    ON transactions.id = movements.transaction_id
+INNER JOIN blocks
+    ON blocks.id = transactions.block_id
+GROUP BY movements.address, movements.transaction_id, blocks.height;
+
+CREATE INDEX address_transactions_history ON address_transactions (address, height DESC, transaction_id DESC);
+CREATE INDEX address_transactions_transaction_id ON address_transactions (transaction_id);
@@ -0,0 +1,43 @@
+ALTER TABLE address_transactions
+    ADD COLUMN received_outputs INT NOT NULL DEFAULT 0,
+    ADD COLUMN spent_outputs INT NOT NULL DEFAULT 0;
+
+UPDATE address_transactions
+SET received_outputs = (
+    SELECT COUNT(*)
+    FROM transaction_outputs
+    WHERE transaction_outputs.transaction_id = address_transactions.transaction_id
+    AND transaction_outputs.address = address_transactions.address
+), spent_outputs = (
+    SELECT COUNT(*)
+    FROM transaction_inputs
+    WHERE transaction_inputs.transaction_id = address_transactions.transaction_id
+    AND transaction_inputs.previous_output_address = address_transactions.address
+);
+
+ALTER TABLE address_transactions
+    ALTER COLUMN received_outputs DROP DEFAULT,
+    ALTER COLUMN spent_outputs DROP DEFAULT;
+
+CREATE TABLE address_summaries (
+    address VARCHAR PRIMARY KEY,
+    total_received BIGINT NOT NULL,
+    total_sent BIGINT NOT NULL,
+    transaction_count BIGINT NOT NULL,
+    utxo_count BIGINT NOT NULL,
+    first_seen_height BIGINT NOT NULL,
+    last_seen_height BIGINT NOT NULL
+);
+
+INSERT INTO address_summaries
+(address, total_received, total_sent, transaction_count, utxo_count, first_seen_height, last_seen_height)
+SELECT
+    address,
+    SUM(received),
+    SUM(sent),
+    COUNT(*),
+    SUM(received_outputs) - SUM(spent_outputs),
+    MIN(height),
+    MAX(height)
+FROM address_transactions
+GROUP BY address;
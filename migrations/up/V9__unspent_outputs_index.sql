@@ -0,0 +1 @@
+CREATE INDEX transaction_outputs_unspent_address ON transaction_outputs (address) WHERE spent_by_transaction_id IS NULL;
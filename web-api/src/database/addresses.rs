@@ -0,0 +1,115 @@
+use crate::database::{Connection, Result};
+use tokio_postgres::Row;
+
+#[derive(Debug)]
+pub struct AddressSummary {
+    pub total_received: i64,
+    pub total_sent: i64,
+    pub transaction_count: i64,
+    pub utxo_count: i64,
+    pub first_seen_height: i64,
+    pub last_seen_height: i64,
+}
+
+impl AddressSummary {
+    pub fn from_row(row: Row) -> Result<Self> {
+        Ok(Self {
+            total_received: row.try_get("total_received")?,
+            total_sent: row.try_get("total_sent")?,
+            transaction_count: row.try_get("transaction_count")?,
+            utxo_count: row.try_get("utxo_count")?,
+            first_seen_height: row.try_get("first_seen_height")?,
+            last_seen_height: row.try_get("last_seen_height")?,
+        })
+    }
+}
+
+pub async fn fetch_address_summary(
+    db: &Connection,
+    address: &str,
+) -> Result<Option<AddressSummary>> {
+    let select_query = "
+        SELECT *
+        FROM address_summaries
+        WHERE address = $1
+    ";
+
+    let summary = db.query_opt(select_query, &[&address]).await?;
+
+    summary.map(AddressSummary::from_row).transpose()
+}
+
+#[derive(Debug)]
+pub struct Utxo {
+    pub txid: Vec<u8>,
+    pub index: i64,
+    pub value: i64,
+    pub height: i64,
+    pub script: Vec<u8>,
+}
+
+impl Utxo {
+    pub fn from_row(row: Row) -> Result<Self> {
+        Ok(Self {
+            txid: row.try_get("txid")?,
+            index: row.try_get("index")?,
+            value: row.try_get("value")?,
+            height: row.try_get("height")?,
+            script: row.try_get("script")?,
+        })
+    }
+}
+
+pub async fn fetch_utxos_for_address(
+    db: &Connection,
+    address: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Utxo>> {
+    let select_query = "
+        SELECT
+            COALESCE(transactions.txid, transactions.wtxid) AS txid,
+            transaction_outputs.index,
+            transaction_outputs.value,
+            blocks.height,
+            transaction_outputs.script
+        FROM transaction_outputs
+        INNER JOIN transactions
+            ON transactions.id = transaction_outputs.transaction_id
+        INNER JOIN blocks
+            ON blocks.id = transactions.block_id
+        WHERE transaction_outputs.address = $1
+        AND transaction_outputs.spent_by_transaction_id IS NULL
+        ORDER BY blocks.height DESC, transaction_outputs.id DESC
+        LIMIT $2 OFFSET $3
+    ";
+
+    let utxos = db.query(select_query, &[&address, &limit, &offset]).await?;
+
+    utxos.into_iter().map(Utxo::from_row).collect()
+}
+
+/// Fetches addresses we've seen that start with `prefix`, which must only contain
+/// alphanumeric characters.
+pub async fn fetch_addresses_by_prefix(
+    db: &Connection,
+    prefix: &str,
+    limit: i64,
+) -> Result<Vec<String>> {
+    let select_query = "
+        SELECT address
+        FROM address_summaries
+        WHERE address LIKE $1
+        ORDER BY address ASC
+        LIMIT $2
+    ";
+
+    let addresses = db
+        .query(select_query, &[&format!("{prefix}%"), &limit])
+        .await?;
+
+    addresses
+        .into_iter()
+        .map(|row| Ok(row.try_get("address")?))
+        .collect()
+}
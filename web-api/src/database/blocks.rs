@@ -5,6 +5,8 @@ use tokio_postgres::Row;
 #[derive(Debug)]
 pub struct Block {
     pub hash: Vec<u8>,
+    pub previous_block_hash: Option<Vec<u8>>,
+    pub next_block_hash: Option<Vec<u8>>,
     pub id: i64,
     pub height: i64,
     pub version: i32,
@@ -14,12 +16,15 @@ pub struct Block {
     pub bits: i32,
     pub nonce: u32,
     pub difficulty: i64,
+    pub stats: BlockStats,
 }
 
 impl Block {
     pub fn from_row(row: Row) -> Result<Self> {
         Ok(Self {
             hash: row.try_get("hash")?,
+            previous_block_hash: row.try_get("previous_block_hash")?,
+            next_block_hash: row.try_get("next_block_hash")?,
             id: row.try_get("id")?,
             height: row.try_get("height")?,
             version: row.try_get("version")?,
@@ -29,31 +34,67 @@ impl Block {
             bits: row.try_get("bits")?,
             nonce: row.try_get::<_, i32>("nonce")? as u32, // TODO
             difficulty: row.try_get("difficulty")?,
+            stats: BlockStats::from_row(&row)?,
         })
     }
 }
 
+/// Statistics calculated by the indexer as it connects each block, fee statistics are unset
+/// until the fees of every transaction in the block are known.
+#[derive(Debug)]
+pub struct BlockStats {
+    pub weight: i64,
+    pub tx_count: i64,
+    pub total_fee: Option<i64>,
+    pub subsidy: i64,
+    pub min_fee_rate: Option<f64>,
+    pub median_fee_rate: Option<f64>,
+    pub max_fee_rate: Option<f64>,
+    /// The 10th, 25th, 50th, 75th and 90th percentile fee rates.
+    pub fee_rate_percentiles: Option<Vec<f64>>,
+    pub input_count: i64,
+    pub output_count: i64,
+    pub segwit_count: i64,
+    pub taproot_count: i64,
+}
+
+impl BlockStats {
+    pub fn from_row(row: &Row) -> Result<Self> {
+        Ok(Self {
+            weight: row.try_get("weight")?,
+            tx_count: row.try_get("tx_count")?,
+            total_fee: row.try_get("total_fee")?,
+            subsidy: row.try_get("subsidy")?,
+            min_fee_rate: row.try_get("min_fee_rate")?,
+            median_fee_rate: row.try_get("median_fee_rate")?,
+            max_fee_rate: row.try_get("max_fee_rate")?,
+            fee_rate_percentiles: row.try_get("fee_rate_percentiles")?,
+            input_count: row.try_get("input_count")?,
+            output_count: row.try_get("output_count")?,
+            segwit_count: row.try_get("segwit_count")?,
+            taproot_count: row.try_get("taproot_count")?,
+        })
+    }
+}
+
+/// Fetches the height of the last block the indexer connected to the chain, blocks above it may
+/// already be stored but aren't yet part of the chain.
 pub async fn fetch_height(db: &Connection) -> Result<u64> {
-    let row = db
-        .query_one("SELECT MAX(height) AS height FROM blocks", &[])
-        .await?;
+    let row = db.query_one("SELECT height FROM sync_state", &[]).await?;
     let height: i64 = row.try_get("height")?;
     Ok(u64::try_from(height)?)
 }
 
-pub type TransactionCount = i64;
-pub type TransactionWeight = rust_decimal::Decimal;
-
 pub async fn fetch_latest_blocks(
     db: &Connection,
     count: i64,
     offset: i64,
-) -> Result<Vec<(Block, TransactionCount, TransactionWeight, Vec<u8>)>> {
+) -> Result<Vec<(Block, Vec<u8>)>> {
     let query = "
         SELECT
             blocks.*,
-            tx.count AS tx_count,
-            tx.weight AS tx_weight,
+            next_blocks.hash AS next_block_hash,
+            block_stats.*,
             (
                 SELECT script
                 FROM transactions
@@ -64,13 +105,15 @@ pub async fn fetch_latest_blocks(
                 LIMIT 1
             ) AS coinbase_script
         FROM blocks
+        INNER JOIN block_stats
+            ON block_stats.block_id = blocks.id
         LEFT JOIN LATERAL (
-            SELECT
-                SUM(transactions.weight) AS weight,
-                COUNT(transactions.id) AS count
-            FROM transactions
-            WHERE transactions.block_id = blocks.id
-        ) tx ON true
+            SELECT hash
+            FROM blocks next_blocks
+            WHERE next_blocks.previous_block_id = blocks.id
+            ORDER BY next_blocks.id DESC
+            LIMIT 1
+        ) next_blocks ON true
         ORDER BY blocks.height DESC
         LIMIT $1 OFFSET $2
     ";
@@ -80,22 +123,81 @@ pub async fn fetch_latest_blocks(
     blocks
         .into_iter()
         .map(|row| {
-            let tx_count = row.try_get("tx_count")?;
-            let tx_weight = row.try_get("tx_weight")?;
             let coinbase_script = row.try_get("coinbase_script")?;
-            Ok((Block::from_row(row)?, tx_count, tx_weight, coinbase_script))
+            Ok((Block::from_row(row)?, coinbase_script))
         })
         .collect::<Result<Vec<_>>>()
 }
 
 pub async fn fetch_block_by_height(db: &Connection, height: i64) -> Result<Option<Block>> {
     let query = "
-        SELECT *
+        SELECT
+            blocks.*,
+            next_blocks.hash AS next_block_hash,
+            block_stats.*
         FROM blocks
-        WHERE height = $1
+        INNER JOIN block_stats
+            ON block_stats.block_id = blocks.id
+        LEFT JOIN LATERAL (
+            SELECT hash
+            FROM blocks next_blocks
+            WHERE next_blocks.previous_block_id = blocks.id
+            ORDER BY next_blocks.id DESC
+            LIMIT 1
+        ) next_blocks ON true
+        WHERE blocks.height = $1
     ";
 
     let block = db.query_opt(query, &[&height]).await?;
 
     block.map(Block::from_row).transpose()
 }
+
+pub async fn fetch_block_by_hash(db: &Connection, hash: &[u8]) -> Result<Option<Block>> {
+    let query = "
+        SELECT
+            blocks.*,
+            next_blocks.hash AS next_block_hash,
+            block_stats.*
+        FROM blocks
+        INNER JOIN block_stats
+            ON block_stats.block_id = blocks.id
+        LEFT JOIN LATERAL (
+            SELECT hash
+            FROM blocks next_blocks
+            WHERE next_blocks.previous_block_id = blocks.id
+            ORDER BY next_blocks.id DESC
+            LIMIT 1
+        ) next_blocks ON true
+        WHERE blocks.hash = $1
+    ";
+
+    let block = db.query_opt(query, &[&hash]).await?;
+
+    block.map(Block::from_row).transpose()
+}
+
+/// Fetches the hashes of blocks whose displayed hash falls within `start..end`, see
+/// [`crate::hash::prefix_range`].
+pub async fn fetch_block_hashes_by_prefix(
+    db: &Connection,
+    start: &[u8],
+    end: Option<&[u8]>,
+    limit: i64,
+) -> Result<Vec<Vec<u8>>> {
+    let query = "
+        SELECT hash
+        FROM blocks
+        WHERE reversed_hash >= $1
+        AND ($2::BYTEA IS NULL OR reversed_hash < $2)
+        ORDER BY reversed_hash ASC
+        LIMIT $3
+    ";
+
+    let blocks = db.query(query, &[&start, &end, &limit]).await?;
+
+    blocks
+        .into_iter()
+        .map(|row| Ok(row.try_get("hash")?))
+        .collect()
+}
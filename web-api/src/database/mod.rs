@@ -1,3 +1,4 @@
+pub mod addresses;
 pub mod blocks;
 pub mod transactions;
 
@@ -5,9 +6,20 @@ use crate::config::DatabaseConfig;
 use deadpool_postgres::{Config, CreatePoolError, ManagerConfig, RecyclingMethod, Runtime};
 use std::ops::Deref;
 use std::sync::Arc;
+use thiserror::Error;
 use tokio_postgres::NoTls;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Failed to query database: {0}")]
+    Query(#[from] tokio_postgres::Error),
+    #[error("Database returned an out of range value: {0}")]
+    OutOfRange(#[from] std::num::TryFromIntError),
+    #[error("Database returned an invalid hash: {0}")]
+    InvalidHash(#[from] bitcoin::hashes::Error),
+}
 
 type Connection = deadpool_postgres::Client;
 
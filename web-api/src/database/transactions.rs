@@ -1,4 +1,5 @@
 use crate::database::{Connection, Result};
+use chrono::NaiveDateTime;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer};
 use tokio_postgres::{
@@ -8,12 +9,16 @@ use tokio_postgres::{
 
 #[derive(Debug)]
 pub struct Transaction {
-    pub hash: Vec<u8>,
+    pub txid: Vec<u8>,
+    pub wtxid: Vec<u8>,
     pub version: i32,
     pub weight: i64,
+    pub vsize: i64,
+    pub fee: Option<i64>,
     pub lock_time: i32,
     pub coinbase: bool,
     pub replace_by_fee: bool,
+    pub position: Option<i32>,
     pub inputs: Json<Vec<TransactionInput>>,
     pub outputs: Json<Vec<TransactionOutput>>,
 }
@@ -21,12 +26,21 @@ pub struct Transaction {
 impl Transaction {
     pub fn from_row(row: Row) -> Result<Self> {
         Ok(Self {
-            hash: row.try_get("hash")?,
+            // transactions indexed before we stored txids separately only have their wtxid
+            // until the indexer has backfilled them
+            txid: match row.try_get("txid")? {
+                Some(txid) => txid,
+                None => row.try_get("wtxid")?,
+            },
+            wtxid: row.try_get("wtxid")?,
             version: row.try_get("version")?,
             weight: row.try_get("weight")?,
+            vsize: row.try_get("vsize")?,
+            fee: row.try_get("fee")?,
             lock_time: row.try_get("lock_time")?,
             coinbase: row.try_get("coinbase")?,
             replace_by_fee: row.try_get("replace_by_fee")?,
+            position: row.try_get("position")?,
             inputs: row.try_get("inputs")?,
             outputs: row.try_get("outputs")?,
         })
@@ -41,7 +55,7 @@ pub struct TransactionInput {
     #[serde(deserialize_with = "trim_hex_prefix")]
     pub script: String,
     #[serde(deserialize_with = "parse_hex_opt")]
-    pub previous_output_tx_hash: Option<Vec<u8>>,
+    pub previous_output_transaction: Option<Vec<u8>>,
     #[serde(rename = "previous_output_item")]
     pub previous_output: Option<TransactionOutput>,
 }
@@ -54,6 +68,10 @@ pub struct TransactionOutput {
     pub script: String,
     pub unspendable: bool,
     pub address: Option<String>,
+    #[serde(default, deserialize_with = "parse_hex_opt")]
+    pub spent_by_tx_hash: Option<Vec<u8>>,
+    pub spent_by_input_index: Option<i64>,
+    pub spent_height: Option<i64>,
 }
 
 fn parse_hex_opt<'de, D: Deserializer<'de>>(
@@ -95,118 +113,319 @@ pub async fn fetch_transactions_for_block(
     id: i64,
     limit: i64,
     offset: i64,
-) -> Result<(i64, Vec<Transaction>)> {
-    let count_query = "
-        SELECT COUNT(*) AS count
-        FROM transactions
-        WHERE transactions.block_id = $1
-    ";
-
-    let count_query_params: &[&(dyn ToSql + Sync)] = &[&id];
-
+) -> Result<Vec<Transaction>> {
     let select_query = "
         SELECT
             transactions.*,
             (
-                SELECT JSON_AGG(transaction_inputs)
+                SELECT JSON_AGG(transaction_inputs ORDER BY transaction_inputs.index)
                 FROM (
                     SELECT
-                        pot.hash AS previous_output_tx_hash,
                         ROW_TO_JSON(po) AS previous_output_item,
                         transaction_inputs.*
                     FROM transaction_inputs
-                    LEFT JOIN transactions pot
-                        ON pot.hash = transaction_inputs.previous_output_transaction
                     LEFT JOIN transaction_outputs po
-                    	ON po.transaction_id = pot.id
-                    	AND po.index = transaction_inputs.previous_output_index
+                        ON po.id = transaction_inputs.previous_output_id
                     WHERE transactions.id = transaction_inputs.transaction_id
                 ) transaction_inputs
             ) AS inputs,
             (
-                SELECT JSON_AGG(transaction_outputs.*)
-                FROM transaction_outputs
-                WHERE transactions.id = transaction_outputs.transaction_id
+                SELECT JSON_AGG(transaction_outputs ORDER BY transaction_outputs.index)
+                FROM (
+                    SELECT
+                        COALESCE(spending_transactions.txid, spending_transactions.wtxid) AS spent_by_tx_hash,
+                        transaction_outputs.*
+                    FROM transaction_outputs
+                    LEFT JOIN transactions spending_transactions
+                        ON spending_transactions.id = transaction_outputs.spent_by_transaction_id
+                    WHERE transactions.id = transaction_outputs.transaction_id
+                ) transaction_outputs
             ) AS outputs
         FROM transactions
         WHERE transactions.block_id = $1
-        ORDER BY transactions.id ASC
+        ORDER BY transactions.position ASC
         LIMIT $2 OFFSET $3
     ";
 
-    let select_query_params: &[&(dyn ToSql + Sync)] = &[&id, &limit, &offset];
+    let transactions = db.query(select_query, &[&id, &limit, &offset]).await?;
+
+    transactions
+        .into_iter()
+        .map(Transaction::from_row)
+        .collect()
+}
+
+/// Fetches the txid of every transaction in the given block, in block order.
+pub async fn fetch_transaction_ids_for_block(db: &Connection, id: i64) -> Result<Vec<Vec<u8>>> {
+    let select_query = "
+        SELECT COALESCE(txid, wtxid) AS txid
+        FROM transactions
+        WHERE block_id = $1
+        ORDER BY position ASC
+    ";
+
+    let transactions = db.query(select_query, &[&id]).await?;
+
+    transactions
+        .into_iter()
+        .map(|row| Ok(row.try_get("txid")?))
+        .collect()
+}
+
+/// A position in an address' history, ordered by the height of the block and then the
+/// position of the transaction within it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryCursor {
+    pub height: i64,
+    pub position: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HistoryPage {
+    /// The most recent transactions.
+    Latest,
+    /// Transactions older than the cursor.
+    After(HistoryCursor),
+    /// Transactions newer than the cursor.
+    Before(HistoryCursor),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// The address only received funds.
+    Received,
+    /// The address spent funds and paid at least some of them to another address.
+    Sent,
+    /// The address spent funds and every spendable output paid back to it.
+    #[serde(rename = "self")]
+    SelfTransfer,
+}
+
+/// Narrows down an address' history. Values are compared against the net amount the
+/// transaction moved in or out of the address.
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    pub direction: Option<Direction>,
+    pub min_value: Option<i64>,
+    pub max_value: Option<i64>,
+    pub min_height: Option<i64>,
+    pub max_height: Option<i64>,
+    pub start_time: Option<NaiveDateTime>,
+    pub end_time: Option<NaiveDateTime>,
+}
+
+impl HistoryFilter {
+    pub fn is_empty(&self) -> bool {
+        self.direction.is_none()
+            && self.min_value.is_none()
+            && self.max_value.is_none()
+            && self.min_height.is_none()
+            && self.max_height.is_none()
+            && self.start_time.is_none()
+            && self.end_time.is_none()
+    }
+
+    /// Builds the conditions for this filter, appending any values they reference to
+    /// `params`.
+    fn conditions<'a>(&'a self, params: &mut Vec<&'a (dyn ToSql + Sync)>) -> String {
+        let mut conditions = String::new();
+
+        let mut push = |condition: &str, param: &'a (dyn ToSql + Sync)| {
+            params.push(param);
+            conditions.push_str(&condition.replace('?', &format!("${}", params.len())));
+        };
+
+        if let Some(min_value) = &self.min_value {
+            push(
+                " AND ABS(address_transactions.received - address_transactions.sent) >= ?",
+                min_value,
+            );
+        }
+
+        if let Some(max_value) = &self.max_value {
+            push(
+                " AND ABS(address_transactions.received - address_transactions.sent) <= ?",
+                max_value,
+            );
+        }
+
+        if let Some(min_height) = &self.min_height {
+            push(" AND address_transactions.height >= ?", min_height);
+        }
 
-    let (count, transactions) = tokio::try_join!(
-        db.query_one(count_query, count_query_params),
-        db.query(select_query, select_query_params)
-    )?;
+        if let Some(max_height) = &self.max_height {
+            push(" AND address_transactions.height <= ?", max_height);
+        }
 
-    Ok((
-        count.try_get("count")?,
-        transactions
-            .into_iter()
-            .map(Transaction::from_row)
-            .collect::<Result<_>>()?,
-    ))
+        if let Some(start_time) = &self.start_time {
+            push(" AND blocks.timestamp >= ?", start_time);
+        }
+
+        if let Some(end_time) = &self.end_time {
+            push(" AND blocks.timestamp <= ?", end_time);
+        }
+
+        let pays_elsewhere = "
+            EXISTS (
+                SELECT 1
+                FROM transaction_outputs
+                WHERE transaction_outputs.transaction_id = address_transactions.transaction_id
+                AND NOT transaction_outputs.unspendable
+                AND transaction_outputs.address IS DISTINCT FROM address_transactions.address
+            )
+        ";
+
+        match self.direction {
+            Some(Direction::Received) => conditions.push_str(" AND address_transactions.sent = 0"),
+            Some(Direction::Sent) => conditions.push_str(&format!(
+                " AND address_transactions.sent > 0 AND {pays_elsewhere}"
+            )),
+            Some(Direction::SelfTransfer) => conditions.push_str(&format!(
+                " AND address_transactions.sent > 0 AND NOT {pays_elsewhere}"
+            )),
+            None => {}
+        }
+
+        conditions
+    }
 }
 
+#[derive(Debug)]
+pub struct AddressTransaction {
+    pub cursor: HistoryCursor,
+    pub transaction: Transaction,
+}
+
+/// Fetches up to `limit` transactions matching `filter` from an address' history, always
+/// returned newest first.
 pub async fn fetch_transactions_for_address(
     db: &Connection,
     address: &str,
-) -> Result<Vec<Transaction>> {
-    let select_query = "
+    filter: &HistoryFilter,
+    page: HistoryPage,
+    limit: i64,
+) -> Result<Vec<AddressTransaction>> {
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&address, &limit];
+
+    let (cursor, order) = match &page {
+        HistoryPage::Latest => (String::new(), "DESC"),
+        HistoryPage::After(cursor) => {
+            params.push(&cursor.height);
+            params.push(&cursor.position);
+            (
+                " AND (address_transactions.height, address_transactions.position) < ($3, $4)"
+                    .to_string(),
+                "DESC",
+            )
+        }
+        HistoryPage::Before(cursor) => {
+            params.push(&cursor.height);
+            params.push(&cursor.position);
+            (
+                " AND (address_transactions.height, address_transactions.position) > ($3, $4)"
+                    .to_string(),
+                "ASC",
+            )
+        }
+    };
+
+    let conditions = filter.conditions(&mut params);
+
+    let select_query = format!(
+        "
         SELECT
-	            transactions.*,
-	            (
-	                SELECT JSON_AGG(transaction_inputs)
-	                FROM (
-	                    SELECT
-                            pot.hash AS previous_output_tx_hash,
-	                        ROW_TO_JSON(po) AS previous_output_item,
-	                        transaction_inputs.*
-	                    FROM transaction_inputs
-                        LEFT JOIN transactions pot
-                            ON pot.hash = transaction_inputs.previous_output_transaction
-                        LEFT JOIN transaction_outputs po
-                    	    ON po.transaction_id = pot.id
-                    	    AND po.index = transaction_inputs.previous_output_index
-	                    WHERE transactions.id = transaction_inputs.transaction_id
-	                ) transaction_inputs
-	            ) AS inputs,
-	            (
-	                SELECT JSON_AGG(transaction_outputs.*)
-	                FROM transaction_outputs
-	                WHERE transactions.id = transaction_outputs.transaction_id
-	            ) AS outputs
-	        FROM transactions
-	        WHERE transactions.id IN (
-	        	SELECT transaction_outputs.transaction_id
-                    FROM transaction_outputs
-                    WHERE transaction_outputs.address = $1
-	        	UNION
-	        	SELECT transaction_inputs.transaction_id
+            address_transactions.height AS history_height,
+            address_transactions.position AS history_position,
+            transactions.*,
+            (
+                SELECT JSON_AGG(transaction_inputs ORDER BY transaction_inputs.index)
+                FROM (
+                    SELECT
+                        ROW_TO_JSON(po) AS previous_output_item,
+                        transaction_inputs.*
                     FROM transaction_inputs
-                    LEFT JOIN transactions pot
-                        ON pot.hash = transaction_inputs.previous_output_transaction
                     LEFT JOIN transaction_outputs po
-                    	ON po.transaction_id = pot.id
-                    	AND po.index = transaction_inputs.previous_output_index
-                    WHERE po.address = $1
-	        )
-            ORDER BY transactions.id DESC
-    ";
+                        ON po.id = transaction_inputs.previous_output_id
+                    WHERE transactions.id = transaction_inputs.transaction_id
+                ) transaction_inputs
+            ) AS inputs,
+            (
+                SELECT JSON_AGG(transaction_outputs ORDER BY transaction_outputs.index)
+                FROM (
+                    SELECT
+                        COALESCE(spending_transactions.txid, spending_transactions.wtxid) AS spent_by_tx_hash,
+                        transaction_outputs.*
+                    FROM transaction_outputs
+                    LEFT JOIN transactions spending_transactions
+                        ON spending_transactions.id = transaction_outputs.spent_by_transaction_id
+                    WHERE transactions.id = transaction_outputs.transaction_id
+                ) transaction_outputs
+            ) AS outputs
+        FROM address_transactions
+        INNER JOIN transactions
+            ON transactions.id = address_transactions.transaction_id
+        INNER JOIN blocks
+            ON blocks.id = transactions.block_id
+        WHERE address_transactions.address = $1
+        {cursor}{conditions}
+        ORDER BY address_transactions.height {order}, address_transactions.position {order}
+        LIMIT $2
+    "
+    );
 
-    let transactions = db.query(select_query, &[&address]).await?;
+    let mut transactions = db.query(&select_query, &params).await?;
+
+    if matches!(page, HistoryPage::Before(_)) {
+        transactions.reverse();
+    }
 
     transactions
         .into_iter()
-        .map(Transaction::from_row)
+        .map(|row| {
+            Ok(AddressTransaction {
+                cursor: HistoryCursor {
+                    height: row.try_get("history_height")?,
+                    position: row.try_get("history_position")?,
+                },
+                transaction: Transaction::from_row(row)?,
+            })
+        })
         .collect()
 }
 
+/// Counts every transaction in an address' history matching `filter`.
+pub async fn count_transactions_for_address(
+    db: &Connection,
+    address: &str,
+    filter: &HistoryFilter,
+) -> Result<i64> {
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&address];
+
+    let conditions = filter.conditions(&mut params);
+
+    let count_query = format!(
+        "
+        SELECT COUNT(*) AS count
+        FROM address_transactions
+        INNER JOIN transactions
+            ON transactions.id = address_transactions.transaction_id
+        INNER JOIN blocks
+            ON blocks.id = transactions.block_id
+        WHERE address_transactions.address = $1
+        {conditions}
+    "
+    );
+
+    Ok(db
+        .query_one(&count_query, &params)
+        .await?
+        .try_get("count")?)
+}
+
 pub struct TransactionWithDetails {
-    pub input_total_value: rust_decimal::Decimal,
-    pub output_total_value: rust_decimal::Decimal,
+    pub input_total_value: i64,
+    pub output_total_value: i64,
     pub transaction: Transaction,
 }
 
@@ -219,17 +438,12 @@ pub async fn fetch_latest_transactions(
             JSON_BUILD_ARRAY() AS inputs,
             JSON_BUILD_ARRAY() AS outputs,
             (
-                SELECT SUM(po.value)
+                SELECT SUM(input.previous_output_value)::BIGINT
                 FROM transaction_inputs input
-                LEFT JOIN transactions pot
-                    ON pot.hash = input.previous_output_transaction
-                LEFT JOIN transaction_outputs po
-                    ON po.transaction_id = pot.id
-                    AND po.index = input.previous_output_index
                 WHERE input.transaction_id = transactions.id
             ) AS input_total_value,
             (
-                SELECT SUM(out.value)
+                SELECT SUM(out.value)::BIGINT
                 FROM transaction_outputs out
                 WHERE out.transaction_id = transactions.id
             ) AS output_total_value
@@ -256,39 +470,103 @@ pub async fn fetch_latest_transactions(
         .collect()
 }
 
+pub struct TransactionWithBlock {
+    pub block_height: i64,
+    pub block_hash: Vec<u8>,
+    pub block_timestamp: NaiveDateTime,
+    pub transaction: Transaction,
+}
+
 pub async fn fetch_transaction_by_hash(
     db: &Connection,
     hash: &[u8],
-) -> Result<Option<Transaction>> {
+) -> Result<Option<TransactionWithBlock>> {
     let select_query = "
         SELECT
 	            transactions.*,
+	            blocks.height AS block_height,
+	            blocks.hash AS block_hash,
+	            blocks.timestamp AS block_timestamp,
 	            (
-	                SELECT JSON_AGG(transaction_inputs)
+	                SELECT JSON_AGG(transaction_inputs ORDER BY transaction_inputs.index)
 	                FROM (
 	                    SELECT
-                            pot.hash AS previous_output_tx_hash,
 	                        ROW_TO_JSON(po) AS previous_output_item,
 	                        transaction_inputs.*
 	                    FROM transaction_inputs
-	                    LEFT JOIN transactions pot
-                            ON pot.hash = transaction_inputs.previous_output_transaction
-                        LEFT JOIN transaction_outputs po
-                            ON po.transaction_id = pot.id
-                            AND po.index = transaction_inputs.previous_output_index
+	                    LEFT JOIN transaction_outputs po
+	                        ON po.id = transaction_inputs.previous_output_id
 	                    WHERE transactions.id = transaction_inputs.transaction_id
 	                ) transaction_inputs
 	            ) AS inputs,
 	            (
-	                SELECT JSON_AGG(transaction_outputs.*)
-	                FROM transaction_outputs
-	                WHERE transactions.id = transaction_outputs.transaction_id
+	                SELECT JSON_AGG(transaction_outputs ORDER BY transaction_outputs.index)
+	                FROM (
+	                    SELECT
+	                        COALESCE(spending_transactions.txid, spending_transactions.wtxid) AS spent_by_tx_hash,
+	                        transaction_outputs.*
+	                    FROM transaction_outputs
+	                    LEFT JOIN transactions spending_transactions
+	                        ON spending_transactions.id = transaction_outputs.spent_by_transaction_id
+	                    WHERE transactions.id = transaction_outputs.transaction_id
+	                ) transaction_outputs
 	            ) AS outputs
 	        FROM transactions
-	        WHERE transactions.hash = $1
+	        INNER JOIN blocks
+	            ON blocks.id = transactions.block_id
+	        WHERE transactions.txid = $1
+	        OR transactions.wtxid = $1
+    ";
+
+    let transaction = db.query_opt(select_query, &[&hash]).await?;
+
+    transaction
+        .map(|tx| {
+            Ok(TransactionWithBlock {
+                block_height: tx.try_get("block_height")?,
+                block_hash: tx.try_get("block_hash")?,
+                block_timestamp: tx.try_get("block_timestamp")?,
+                transaction: Transaction::from_row(tx)?,
+            })
+        })
+        .transpose()
+}
+
+/// Resolves either a txid or a wtxid to the transaction's txid.
+pub async fn fetch_txid_by_hash(db: &Connection, hash: &[u8]) -> Result<Option<Vec<u8>>> {
+    let select_query = "
+        SELECT COALESCE(txid, wtxid) AS txid
+        FROM transactions
+        WHERE txid = $1
+        OR wtxid = $1
     ";
 
     let transaction = db.query_opt(select_query, &[&hash]).await?;
 
-    transaction.map(Transaction::from_row).transpose()
+    Ok(transaction.map(|row| row.try_get("txid")).transpose()?)
+}
+
+/// Fetches the txids of transactions whose displayed txid falls within `start..end`, see
+/// [`crate::hash::prefix_range`].
+pub async fn fetch_txids_by_prefix(
+    db: &Connection,
+    start: &[u8],
+    end: Option<&[u8]>,
+    limit: i64,
+) -> Result<Vec<Vec<u8>>> {
+    let select_query = "
+        SELECT txid
+        FROM transactions
+        WHERE reversed_txid >= $1
+        AND ($2::BYTEA IS NULL OR reversed_txid < $2)
+        ORDER BY reversed_txid ASC
+        LIMIT $3
+    ";
+
+    let transactions = db.query(select_query, &[&start, &end, &limit]).await?;
+
+    transactions
+        .into_iter()
+        .map(|row| Ok(row.try_get("txid")?))
+        .collect()
 }
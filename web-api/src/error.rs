@@ -0,0 +1,66 @@
+//! Errors that can be returned from any handler, rendered as a JSON body with an appropriate
+//! status code.
+
+use crate::{database, middleware::logging::GenericError};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("Invalid {0}")]
+    BadRequest(&'static str),
+    #[error("Failed to get database connection: {0}")]
+    Pool(#[from] deadpool_postgres::PoolError),
+    #[error("{0}")]
+    Database(#[from] database::Error),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Database(database::Error::Query(e)) if e.is_closed() => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl GenericError for ApiError {}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+
+        // don't leak the internals of server errors to the client, they'll be logged instead
+        let error = if status.is_server_error() {
+            status
+                .canonical_reason()
+                .unwrap_or("Internal Server Error")
+                .to_string()
+        } else {
+            self.to_string()
+        };
+
+        let mut response = (status, Json(ErrorBody { error })).into_response();
+        response
+            .extensions_mut()
+            .insert::<Box<dyn GenericError>>(Box::new(self));
+        response
+    }
+}
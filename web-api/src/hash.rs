@@ -0,0 +1,85 @@
+//! Hashes are stored in the database in their internal byte order, but everything outside of
+//! it, including bitcoind's RPC, displays them byte-reversed.
+
+/// Encodes a hash stored in internal byte order as hex in display order.
+pub fn encode(hash: &[u8]) -> String {
+    let mut hash = hash.to_vec();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// Parses a 32-byte hash given as hex in display order into internal byte order.
+pub fn decode(hash: &str) -> Option<Vec<u8>> {
+    let mut hash = hex::decode(hash).ok().filter(|hash| hash.len() == 32)?;
+    hash.reverse();
+    Some(hash)
+}
+
+/// Converts a hex prefix of a hash in display order into the range of display order bytes
+/// that it covers, for matching against the reversed hash. The end of the range is exclusive,
+/// and there isn't one if the prefix is all `f`s. Returns `None` if the prefix isn't hex.
+pub fn prefix_range(prefix: &str) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    let mut nibbles = prefix
+        .chars()
+        .map(|c| c.to_digit(16).map(|v| v as u8))
+        .collect::<Option<Vec<_>>>()?;
+
+    let start = pack_nibbles(&nibbles);
+
+    // increment the prefix as a number, dropping any nibbles that overflow
+    while let Some(last) = nibbles.pop() {
+        if last < 0xf {
+            nibbles.push(last + 1);
+            return Some((start, Some(pack_nibbles(&nibbles))));
+        }
+    }
+
+    Some((start, None))
+}
+
+fn pack_nibbles(nibbles: &[u8]) -> Vec<u8> {
+    nibbles
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair.get(1).copied().unwrap_or(0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_range_covers_prefix() {
+        assert_eq!(prefix_range("ab"), Some((vec![0xab], Some(vec![0xac]))));
+        assert_eq!(prefix_range("0f"), Some((vec![0x0f], Some(vec![0x10]))));
+        assert_eq!(prefix_range("AB"), prefix_range("ab"));
+    }
+
+    #[test]
+    fn prefix_range_pads_odd_length_prefix() {
+        assert_eq!(
+            prefix_range("abc"),
+            Some((vec![0xab, 0xc0], Some(vec![0xab, 0xd0])))
+        );
+
+        // incrementing the last nibble carries into the one before it
+        assert_eq!(
+            prefix_range("abf"),
+            Some((vec![0xab, 0xf0], Some(vec![0xac])))
+        );
+    }
+
+    #[test]
+    fn prefix_range_of_all_fs_is_unbounded() {
+        assert_eq!(prefix_range("ff"), Some((vec![0xff], None)));
+        assert_eq!(prefix_range("fff"), Some((vec![0xff, 0xf0], None)));
+        assert_eq!(prefix_range(""), Some((vec![], None)));
+    }
+
+    #[test]
+    fn prefix_range_rejects_non_hex() {
+        assert_eq!(prefix_range("abg"), None);
+        assert_eq!(prefix_range("0x12"), None);
+        assert_eq!(prefix_range("é"), None);
+    }
+}
@@ -1,5 +1,7 @@
 mod config;
 mod database;
+mod error;
+mod hash;
 mod methods;
 mod middleware;
 
@@ -1,16 +1,338 @@
 use crate::{
-    database::transactions::fetch_transactions_for_address, methods::block::Transaction, Database,
+    database::{
+        addresses::{fetch_address_summary, fetch_utxos_for_address},
+        transactions::{
+            count_transactions_for_address, fetch_transactions_for_address, Direction,
+            HistoryCursor, HistoryFilter, HistoryPage,
+        },
+    },
+    error::ApiError,
+    hash,
+    methods::block::Transaction,
+    Database,
 };
-use axum::{extract::Path, Extension, Json};
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use bitcoin::{address::NetworkUnchecked, Address};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    limit: Option<u32>,
+    after: Option<String>,
+    before: Option<String>,
+    direction: Option<Direction>,
+    min_value: Option<i64>,
+    max_value: Option<i64>,
+    min_height: Option<i64>,
+    max_height: Option<i64>,
+    /// Unix timestamp of the earliest block to include.
+    start_time: Option<i64>,
+    /// Unix timestamp of the latest block to include.
+    end_time: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct AddressHistory {
+    transactions: Vec<Transaction>,
+    total: i64,
+    /// Cursor to pass as `after` to fetch the next (older) page, if there is one.
+    next: Option<String>,
+    /// Cursor to pass as `before` to fetch the previous (newer) page, if there is one.
+    previous: Option<String>,
+}
+
+/// Rejects anything that isn't an address on any network, rather than reporting it as unused.
+fn validate_address(address: &str) -> Result<(), ApiError> {
+    address
+        .parse::<Address<NetworkUnchecked>>()
+        .map(|_| ())
+        .map_err(|_| ApiError::BadRequest("address"))
+}
+
+/// Cursors are passed around as `<height>:<position>`.
+fn parse_cursor(cursor: &str) -> Result<HistoryCursor, ApiError> {
+    cursor
+        .split_once(':')
+        .and_then(|(height, position)| {
+            Some(HistoryCursor {
+                height: height.parse().ok()?,
+                position: position.parse().ok()?,
+            })
+        })
+        .ok_or(ApiError::BadRequest("cursor"))
+}
+
+fn format_cursor(cursor: HistoryCursor) -> String {
+    format!("{}:{}", cursor.height, cursor.position)
+}
+
+/// Drops the extra item fetched past `limit` to tell whether there's another page beyond this
+/// one, returning the cursors of the next (older) and previous (newer) pages. `items` are
+/// newest first.
+fn paginate<T>(
+    page: HistoryPage,
+    items: &mut Vec<T>,
+    limit: usize,
+    cursor: impl Fn(&T) -> HistoryCursor,
+) -> (Option<HistoryCursor>, Option<HistoryCursor>) {
+    let has_more = items.len() > limit;
+
+    if has_more {
+        match page {
+            HistoryPage::Before(_) => items.remove(0),
+            HistoryPage::Latest | HistoryPage::After(_) => items.remove(limit),
+        };
+    }
+
+    let (has_next, has_previous) = match page {
+        HistoryPage::Latest => (has_more, false),
+        HistoryPage::After(_) => (has_more, true),
+        HistoryPage::Before(_) => (true, has_more),
+    };
+
+    (
+        items.last().filter(|_| has_next).map(&cursor),
+        items.first().filter(|_| has_previous).map(&cursor),
+    )
+}
+
+fn parse_timestamp(timestamp: i64) -> Result<NaiveDateTime, ApiError> {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|v| v.naive_utc())
+        .ok_or(ApiError::BadRequest("timestamp"))
+}
 
 pub async fn handle(
     Extension(database): Extension<Database>,
     Path(address): Path<String>,
-) -> Json<Vec<Transaction>> {
-    let database = database.get().await.unwrap();
-    let transactions = fetch_transactions_for_address(&database, &address)
-        .await
-        .unwrap();
+    Query(params): Query<HistoryParams>,
+) -> Result<Json<AddressHistory>, ApiError> {
+    validate_address(&address)?;
+
+    let database = database.get().await?;
+
+    let limit = params.limit.unwrap_or(25).clamp(1, 100);
+
+    let page = match (params.after.as_deref(), params.before.as_deref()) {
+        (Some(after), _) => HistoryPage::After(parse_cursor(after)?),
+        (None, Some(before)) => HistoryPage::Before(parse_cursor(before)?),
+        (None, None) => HistoryPage::Latest,
+    };
+
+    let filter = HistoryFilter {
+        direction: params.direction,
+        min_value: params.min_value,
+        max_value: params.max_value,
+        min_height: params.min_height,
+        max_height: params.max_height,
+        start_time: params.start_time.map(parse_timestamp).transpose()?,
+        end_time: params.end_time.map(parse_timestamp).transpose()?,
+    };
+
+    // fetch one extra transaction so we know whether there's another page past this one
+    let mut transactions =
+        fetch_transactions_for_address(&database, &address, &filter, page, i64::from(limit) + 1)
+            .await?;
+
+    // the summary already knows how many transactions the address has in total, so we only
+    // need to count them ourselves when they've been filtered
+    let total = if filter.is_empty() {
+        fetch_address_summary(&database, &address)
+            .await?
+            .map_or(0, |summary| summary.transaction_count)
+    } else {
+        count_transactions_for_address(&database, &address, &filter).await?
+    };
+
+    let (next, previous) = paginate(page, &mut transactions, limit as usize, |v| v.cursor);
+
+    Ok(Json(AddressHistory {
+        next: next.map(format_cursor),
+        previous: previous.map(format_cursor),
+        total,
+        transactions: transactions
+            .into_iter()
+            .map(|v| v.transaction.into())
+            .collect(),
+    }))
+}
+
+#[derive(Serialize)]
+pub struct AddressSummary {
+    address: String,
+    balance: i64,
+    total_received: i64,
+    total_sent: i64,
+    tx_count: i64,
+    utxo_count: i64,
+    first_seen_height: Option<i64>,
+    last_seen_height: Option<i64>,
+}
+
+pub async fn summary(
+    Extension(database): Extension<Database>,
+    Path(address): Path<String>,
+) -> Result<Json<AddressSummary>, ApiError> {
+    validate_address(&address)?;
+
+    let database = database.get().await?;
+    let summary = fetch_address_summary(&database, &address).await?;
+
+    // an address we've never seen is still a valid address, it just hasn't been used yet
+    Ok(Json(match summary {
+        Some(summary) => AddressSummary {
+            address,
+            balance: summary.total_received - summary.total_sent,
+            total_received: summary.total_received,
+            total_sent: summary.total_sent,
+            tx_count: summary.transaction_count,
+            utxo_count: summary.utxo_count,
+            first_seen_height: Some(summary.first_seen_height),
+            last_seen_height: Some(summary.last_seen_height),
+        },
+        None => AddressSummary {
+            address,
+            balance: 0,
+            total_received: 0,
+            total_sent: 0,
+            tx_count: 0,
+            utxo_count: 0,
+            first_seen_height: None,
+            last_seen_height: None,
+        },
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct UtxoParams {
+    limit: Option<u32>,
+    #[serde(default)]
+    offset: u32,
+}
+
+#[derive(Serialize)]
+pub struct Utxo {
+    txid: String,
+    vout: i64,
+    value: i64,
+    height: i64,
+    script: String,
+}
+
+pub async fn utxos(
+    Extension(database): Extension<Database>,
+    Path(address): Path<String>,
+    Query(params): Query<UtxoParams>,
+) -> Result<Json<Vec<Utxo>>, ApiError> {
+    validate_address(&address)?;
+
+    let database = database.get().await?;
+
+    let limit = params.limit.unwrap_or(25).clamp(1, 100);
+    let offset = params.offset;
+
+    let utxos = fetch_utxos_for_address(&database, &address, limit.into(), offset.into()).await?;
+
+    Ok(Json(
+        utxos
+            .into_iter()
+            .map(|utxo| Utxo {
+                txid: hash::encode(&utxo.txid),
+                vout: utxo.index,
+                value: utxo.value,
+                height: utxo.height,
+                script: hex::encode(utxo.script),
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(height: i64, position: i32) -> HistoryCursor {
+        HistoryCursor { height, position }
+    }
+
+    /// A page of `len` transactions, newest first, all from separate blocks.
+    fn history(len: i64) -> Vec<HistoryCursor> {
+        (0..len).rev().map(|height| cursor(height, 0)).collect()
+    }
+
+    #[test]
+    fn parses_cursor() {
+        assert_eq!(parse_cursor("812345:12").unwrap(), cursor(812_345, 12));
+        assert_eq!(format_cursor(cursor(812_345, 12)), "812345:12");
+    }
+
+    #[test]
+    fn rejects_malformed_cursor() {
+        for cursor in [
+            "",
+            "812345",
+            "812345:",
+            ":12",
+            "abc:12",
+            "812345:abc",
+            "1:2:3",
+        ] {
+            assert!(
+                matches!(parse_cursor(cursor), Err(ApiError::BadRequest("cursor"))),
+                "{cursor}"
+            );
+        }
+    }
+
+    #[test]
+    fn latest_page() {
+        // one more than the limit was fetched, so there are older transactions
+        let mut items = history(4);
+        let (next, previous) = paginate(HistoryPage::Latest, &mut items, 3, |v| *v);
+        assert_eq!(items, vec![cursor(3, 0), cursor(2, 0), cursor(1, 0)]);
+        assert_eq!((next, previous), (Some(cursor(1, 0)), None));
+
+        let mut items = history(3);
+        let (next, previous) = paginate(HistoryPage::Latest, &mut items, 3, |v| *v);
+        assert_eq!(items.len(), 3);
+        assert_eq!((next, previous), (None, None));
+    }
+
+    #[test]
+    fn after_page() {
+        let page = HistoryPage::After(cursor(10, 0));
+
+        let mut items = history(4);
+        let (next, previous) = paginate(page, &mut items, 3, |v| *v);
+        assert_eq!(items, vec![cursor(3, 0), cursor(2, 0), cursor(1, 0)]);
+        assert_eq!((next, previous), (Some(cursor(1, 0)), Some(cursor(3, 0))));
+
+        // the last page still leads back to the newer ones
+        let mut items = history(2);
+        let (next, previous) = paginate(page, &mut items, 3, |v| *v);
+        assert_eq!(items.len(), 2);
+        assert_eq!((next, previous), (None, Some(cursor(1, 0))));
+    }
+
+    #[test]
+    fn before_page() {
+        let page = HistoryPage::Before(cursor(0, 0));
+
+        // the extra transaction is the newest, so that's the one dropped
+        let mut items = history(4);
+        let (next, previous) = paginate(page, &mut items, 3, |v| *v);
+        assert_eq!(items, vec![cursor(2, 0), cursor(1, 0), cursor(0, 0)]);
+        assert_eq!((next, previous), (Some(cursor(0, 0)), Some(cursor(2, 0))));
 
-    Json(transactions.into_iter().map(Into::into).collect())
+        // the first page still leads on to the older ones
+        let mut items = history(2);
+        let (next, previous) = paginate(page, &mut items, 3, |v| *v);
+        assert_eq!(items.len(), 2);
+        assert_eq!((next, previous), (Some(cursor(0, 0)), None));
+    }
 }
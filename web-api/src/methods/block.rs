@@ -1,10 +1,14 @@
-use crate::Database;
+use crate::{error::ApiError, hash, Database};
 use axum::extract::{Path, Query};
 use axum::{Extension, Json};
-use bitcoin::blockdata::constants::WITNESS_SCALE_FACTOR;
-use bitcoin::VarInt;
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hash_types::TxMerkleNode;
+use bitcoin::hashes::Hash;
+use bitcoin::{BlockHash, CompactTarget};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[derive(Serialize)]
 pub struct MinedBy {
@@ -27,9 +31,48 @@ pub struct BlockList {
     bits: i32,
     nonce: u32,
     difficulty: i64,
-    weight: u64,
-    tx_count: i64,
     size: i32,
+    previous_block_hash: Option<String>,
+    next_block_hash: Option<String>,
+    confirmations: i64,
+    #[serde(flatten)]
+    stats: BlockStats,
+}
+
+#[derive(Serialize)]
+pub struct BlockStats {
+    weight: i64,
+    tx_count: i64,
+    total_fee: Option<i64>,
+    subsidy: i64,
+    min_fee_rate: Option<f64>,
+    median_fee_rate: Option<f64>,
+    max_fee_rate: Option<f64>,
+    /// The 10th, 25th, 50th, 75th and 90th percentile fee rates, in sat/vB.
+    fee_rate_percentiles: Option<Vec<f64>>,
+    input_count: i64,
+    output_count: i64,
+    segwit_count: i64,
+    taproot_count: i64,
+}
+
+impl From<crate::database::blocks::BlockStats> for BlockStats {
+    fn from(stats: crate::database::blocks::BlockStats) -> Self {
+        Self {
+            weight: stats.weight,
+            tx_count: stats.tx_count,
+            total_fee: stats.total_fee,
+            subsidy: stats.subsidy,
+            min_fee_rate: stats.min_fee_rate,
+            median_fee_rate: stats.median_fee_rate,
+            max_fee_rate: stats.max_fee_rate,
+            fee_rate_percentiles: stats.fee_rate_percentiles,
+            input_count: stats.input_count,
+            output_count: stats.output_count,
+            segwit_count: stats.segwit_count,
+            taproot_count: stats.taproot_count,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -43,52 +86,41 @@ pub struct ListParams {
 pub async fn list(
     Extension(database): Extension<Database>,
     Query(params): Query<ListParams>,
-) -> Json<Vec<BlockList>> {
-    let database = database.get().await.unwrap();
+) -> Result<Json<Vec<BlockList>>, ApiError> {
+    let database = database.get().await?;
 
-    let limit = std::cmp::min(20, std::cmp::max(5, params.limit));
+    let limit = params.limit.clamp(5, 20);
     let offset = params.offset;
 
+    let tip = crate::database::blocks::fetch_height(&database).await?;
+
     let blocks = crate::database::blocks::fetch_latest_blocks(
         &database,
         i64::from(limit),
         i64::from(offset),
     )
-    .await
-    .unwrap();
+    .await?;
 
-    Json(
+    Ok(Json(
         blocks
             .into_iter()
-            .map(|(mut block, tx_count, tx_weight, coinbase_script)| {
-                // TODO: do this on insert
-                block.hash.reverse();
-
-                BlockList {
-                    hash: hex::encode(block.hash),
-                    mined_by: Pool::fetch_from_script(&coinbase_script).map(Into::into),
-                    height: block.height,
-                    version: block.version,
-                    timestamp: block.timestamp,
-                    size: block.size,
-                    bits: block.bits,
-                    nonce: block.nonce,
-                    difficulty: block.difficulty,
-                    weight: (u64::try_from(WITNESS_SCALE_FACTOR).unwrap()
-                        * u64::try_from(VarInt(u64::try_from(tx_count).unwrap()).len()).unwrap())
-                        + u64::try_from(tx_weight.mantissa()).unwrap(),
-                    tx_count,
-                }
+            .map(|(block, coinbase_script)| BlockList {
+                hash: hash::encode(&block.hash),
+                mined_by: Pool::fetch_from_script(&coinbase_script).map(Into::into),
+                height: block.height,
+                version: block.version,
+                timestamp: block.timestamp,
+                size: block.size,
+                bits: block.bits,
+                nonce: block.nonce,
+                difficulty: block.difficulty,
+                previous_block_hash: block.previous_block_hash.as_deref().map(hash::encode),
+                next_block_hash: block.next_block_hash.as_deref().map(hash::encode),
+                confirmations: confirmations(tip, block.height),
+                stats: block.stats.into(),
             })
             .collect(),
-    )
-}
-
-#[derive(Serialize)]
-pub struct GetResponse {
-    tx_count: i64,
-    #[serde(flatten)]
-    block: Block,
+    ))
 }
 
 #[derive(Serialize)]
@@ -104,16 +136,35 @@ pub struct Block {
     difficulty: i64,
     transactions: Vec<Transaction>,
     hash: String,
+    previous_block_hash: Option<String>,
+    next_block_hash: Option<String>,
+    confirmations: i64,
+    #[serde(flatten)]
+    stats: BlockStats,
+}
+
+/// Amount of blocks that have been built on top of the block at `height`, including itself.
+/// Blocks the indexer has stored but not yet connected to the chain have none.
+pub fn confirmations(tip: u64, height: i64) -> i64 {
+    (tip as i64 - height + 1).max(0)
 }
 
 #[derive(Serialize)]
 pub struct Transaction {
     pub hash: String,
+    pub wtxid: String,
     pub version: i32,
     pub weight: i64,
+    pub vsize: i64,
+    /// Unset for coinbases, and for transactions spending outputs we haven't indexed yet.
+    pub fee: Option<i64>,
+    /// Fee paid in sat/vB.
+    pub fee_rate: Option<f64>,
     pub lock_time: i32,
     pub coinbase: bool,
     pub replace_by_fee: bool,
+    /// Index of the transaction within its block, the coinbase always being 0.
+    pub position: Option<i32>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub inputs: Vec<TransactionInput>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
@@ -121,16 +172,19 @@ pub struct Transaction {
 }
 
 impl From<crate::database::transactions::Transaction> for Transaction {
-    fn from(mut tx: crate::database::transactions::Transaction) -> Self {
-        tx.hash.reverse();
-
+    fn from(tx: crate::database::transactions::Transaction) -> Self {
         Transaction {
-            hash: hex::encode(tx.hash),
+            hash: hash::encode(&tx.txid),
+            wtxid: hash::encode(&tx.wtxid),
             version: tx.version,
             weight: tx.weight,
+            vsize: tx.vsize,
+            fee: tx.fee,
+            fee_rate: tx.fee.map(|fee| fee as f64 / tx.vsize as f64),
             lock_time: tx.lock_time,
             coinbase: tx.coinbase,
             replace_by_fee: tx.replace_by_fee,
+            position: tx.position,
             inputs: tx.inputs.0.into_iter().map(Into::into).collect(),
             outputs: tx.outputs.0.into_iter().map(Into::into).collect(),
         }
@@ -162,11 +216,9 @@ impl From<crate::database::transactions::TransactionInput> for TransactionInput
                 tx_index: v.index,
                 output: v.into(),
                 tx_hash: txi
-                    .previous_output_tx_hash
-                    .map(|mut h| {
-                        h.reverse();
-                        hex::encode(h)
-                    })
+                    .previous_output_transaction
+                    .as_deref()
+                    .map(hash::encode)
                     .unwrap_or_default(),
             }),
             script: txi.script,
@@ -181,68 +233,173 @@ pub struct TransactionOutput {
     script: String,
     unspendable: bool,
     address: Option<String>,
+    spent: bool,
+    spent_by: Option<SpentBy>,
 }
 
 impl From<crate::database::transactions::TransactionOutput> for TransactionOutput {
     fn from(txo: crate::database::transactions::TransactionOutput) -> Self {
+        let spent = txo.spent_height.is_some();
+
+        // previous outputs attached to inputs don't come with the spending transaction's hash
+        // since it's the transaction they're being attached to
+        let spent_by = match (
+            txo.spent_by_tx_hash,
+            txo.spent_by_input_index,
+            txo.spent_height,
+        ) {
+            (Some(tx_hash), Some(input_index), Some(height)) => Some(SpentBy {
+                tx_hash: hash::encode(&tx_hash),
+                input_index,
+                height,
+            }),
+            _ => None,
+        };
+
         Self {
             index: txo.index,
             value: txo.value,
             script: txo.script,
             unspendable: txo.unspendable,
             address: txo.address,
+            spent,
+            spent_by,
         }
     }
 }
 
+#[derive(Serialize)]
+pub struct SpentBy {
+    tx_hash: String,
+    input_index: i64,
+    height: i64,
+}
+
 #[derive(Deserialize)]
 pub struct HandleQuery {
     #[serde(default)]
     offset: u32,
 }
 
+/// Blocks can be referred to by either their height or their hash.
+pub enum BlockId {
+    Height(i64),
+    Hash(Vec<u8>),
+}
+
+impl FromStr for BlockId {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 64 {
+            hash::decode(s)
+                .map(Self::Hash)
+                .ok_or(ApiError::BadRequest("block hash"))
+        } else {
+            s.parse()
+                .map(Self::Height)
+                .map_err(|_| ApiError::BadRequest("block height"))
+        }
+    }
+}
+
+async fn fetch_block(
+    database: &deadpool_postgres::Client,
+    id: &str,
+) -> Result<crate::database::blocks::Block, ApiError> {
+    let block = match id.parse()? {
+        BlockId::Height(height) => {
+            crate::database::blocks::fetch_block_by_height(database, height).await?
+        }
+        BlockId::Hash(hash) => {
+            crate::database::blocks::fetch_block_by_hash(database, &hash).await?
+        }
+    };
+
+    block.ok_or(ApiError::NotFound("Block"))
+}
+
 pub async fn handle(
     Extension(database): Extension<Database>,
-    Path(height): Path<i64>,
+    Path(id): Path<String>,
     Query(query): Query<HandleQuery>,
-) -> Json<GetResponse> {
-    let database = database.get().await.unwrap();
+) -> Result<Json<Block>, ApiError> {
+    let database = database.get().await?;
     let offset = i64::from(query.offset);
     let limit = 30;
 
-    let mut block = crate::database::blocks::fetch_block_by_height(&database, height)
-        .await
-        .unwrap()
-        .unwrap();
+    let block = fetch_block(&database, &id).await?;
+    let tip = crate::database::blocks::fetch_height(&database).await?;
 
-    let (count, transactions) = crate::database::transactions::fetch_transactions_for_block(
+    let transactions = crate::database::transactions::fetch_transactions_for_block(
         &database, block.id, limit, offset,
     )
-    .await
-    .unwrap();
-
-    // TODO: do this on insert
-    block.hash.reverse();
+    .await?;
 
     let block = Block {
-        hash: hex::encode(block.hash),
+        hash: hash::encode(&block.hash),
         height: block.height,
         version: block.version,
         size: block.size,
-        merkle_root_hash: hex::encode(block.merkle_root_hash),
+        merkle_root_hash: hash::encode(&block.merkle_root_hash),
         timestamp: block.timestamp,
         bits: block.bits,
         nonce: block.nonce,
         difficulty: block.difficulty,
         transactions: transactions.into_iter().map(Into::into).collect(),
+        previous_block_hash: block.previous_block_hash.as_deref().map(hash::encode),
+        next_block_hash: block.next_block_hash.as_deref().map(hash::encode),
+        confirmations: confirmations(tip, block.height),
+        stats: block.stats.into(),
     };
 
-    Json(GetResponse {
-        tx_count: count,
-        block,
-    })
+    Ok(Json(block))
+}
+
+/// Returns the block's serialised 80 byte header as hex.
+pub async fn header(
+    Extension(database): Extension<Database>,
+    Path(id): Path<String>,
+) -> Result<String, ApiError> {
+    let database = database.get().await?;
+    let block = fetch_block(&database, &id).await?;
+
+    // the genesis block is the only one without a parent once the indexer has filled in the
+    // parents of blocks indexed before we stored them
+    let prev_blockhash = match block.previous_block_hash {
+        Some(hash) => BlockHash::from_slice(&hash).map_err(crate::database::Error::from)?,
+        None if block.height == 0 => BlockHash::all_zeros(),
+        None => return Err(ApiError::NotFound("Previous block")),
+    };
+
+    let header = Header {
+        version: Version::from_consensus(block.version),
+        prev_blockhash,
+        merkle_root: TxMerkleNode::from_slice(&block.merkle_root_hash)
+            .map_err(crate::database::Error::from)?,
+        time: u32::try_from(block.timestamp.timestamp()).map_err(crate::database::Error::from)?,
+        bits: CompactTarget::from_consensus(block.bits as u32),
+        nonce: block.nonce,
+    };
+
+    Ok(serialize_hex(&header))
+}
+
+/// Returns the txid of every transaction in the block, in block order.
+pub async fn txids(
+    Extension(database): Extension<Database>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let database = database.get().await?;
+    let block = fetch_block(&database, &id).await?;
+
+    let txids =
+        crate::database::transactions::fetch_transaction_ids_for_block(&database, block.id).await?;
+
+    Ok(Json(txids.iter().map(|txid| hash::encode(txid)).collect()))
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum Pool {
     Luxor,
     F2Pool,
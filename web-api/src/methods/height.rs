@@ -1,11 +1,9 @@
-use crate::Database;
+use crate::{error::ApiError, Database};
 use axum::Extension;
 
-pub async fn handle(Extension(database): Extension<Database>) -> String {
-    let database = database.get().await.unwrap();
-    let height = crate::database::blocks::fetch_height(&database)
-        .await
-        .unwrap();
+pub async fn handle(Extension(database): Extension<Database>) -> Result<String, ApiError> {
+    let database = database.get().await?;
+    let height = crate::database::blocks::fetch_height(&database).await?;
 
-    height.to_string()
+    Ok(height.to_string())
 }
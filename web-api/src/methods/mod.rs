@@ -4,14 +4,20 @@ use axum::Router;
 mod address;
 mod block;
 mod height;
+mod search;
 mod transaction;
 
 pub fn router() -> Router {
     Router::new()
         .route("/height", get(height::handle))
         .route("/block", get(block::list))
-        .route("/block/:height", get(block::handle))
+        .route("/block/:id", get(block::handle))
+        .route("/block/:id/header", get(block::header))
+        .route("/block/:id/txids", get(block::txids))
         .route("/address/:address", get(address::handle))
+        .route("/address/:address/summary", get(address::summary))
+        .route("/address/:address/utxo", get(address::utxos))
         .route("/tx", get(transaction::list))
         .route("/tx/:hash", get(transaction::handle))
+        .route("/search", get(search::handle))
 }
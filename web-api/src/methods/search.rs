@@ -0,0 +1,140 @@
+use crate::{
+    database::{
+        addresses::fetch_addresses_by_prefix,
+        blocks::{fetch_block_by_hash, fetch_block_by_height, fetch_block_hashes_by_prefix},
+        transactions::{fetch_txid_by_hash, fetch_txids_by_prefix},
+    },
+    error::ApiError,
+    hash, Database,
+};
+use axum::{extract::Query, Extension, Json};
+use bitcoin::{address::NetworkUnchecked, Address};
+use serde::{Deserialize, Serialize};
+
+/// Prefixes shorter than this match far too much to be useful as suggestions.
+const MIN_PREFIX_LENGTH: usize = 4;
+const MAX_SUGGESTIONS: i64 = 10;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityType {
+    Block,
+    Transaction,
+    Address,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    #[serde(rename = "type")]
+    entity_type: EntityType,
+    id: String,
+    url: String,
+}
+
+impl SearchResult {
+    fn block(hash: &[u8]) -> Self {
+        let id = hash::encode(hash);
+
+        Self {
+            entity_type: EntityType::Block,
+            url: format!("/block/{id}"),
+            id,
+        }
+    }
+
+    fn transaction(txid: &[u8]) -> Self {
+        let id = hash::encode(txid);
+
+        Self {
+            entity_type: EntityType::Transaction,
+            url: format!("/tx/{id}"),
+            id,
+        }
+    }
+
+    fn address(address: String) -> Self {
+        Self {
+            entity_type: EntityType::Address,
+            url: format!("/address/{address}"),
+            id: address,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    /// Entities that exactly match the query.
+    matches: Vec<SearchResult>,
+    /// Entities that start with the query, only given if nothing matched exactly.
+    suggestions: Vec<SearchResult>,
+}
+
+pub async fn handle(
+    Extension(database): Extension<Database>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    let q = query.q.trim();
+
+    if q.is_empty() {
+        return Err(ApiError::BadRequest("search query"));
+    }
+
+    let database = database.get().await?;
+
+    let mut matches = Vec::new();
+
+    if let Ok(height) = q.parse::<i64>() {
+        if let Some(block) = fetch_block_by_height(&database, height).await? {
+            matches.push(SearchResult::block(&block.hash));
+        }
+    }
+
+    if let Some(hash) = hash::decode(q) {
+        if let Some(block) = fetch_block_by_hash(&database, &hash).await? {
+            matches.push(SearchResult::block(&block.hash));
+        }
+
+        if let Some(txid) = fetch_txid_by_hash(&database, &hash).await? {
+            matches.push(SearchResult::transaction(&txid));
+        }
+    }
+
+    // any valid address is a match, even if it's never been used
+    if let Ok(address) = q.parse::<Address<NetworkUnchecked>>() {
+        matches.push(SearchResult::address(address.assume_checked().to_string()));
+    }
+
+    let mut suggestions = Vec::new();
+
+    if matches.is_empty() && q.len() >= MIN_PREFIX_LENGTH {
+        if let Some((start, end)) = hash::prefix_range(q) {
+            let end = end.as_deref();
+
+            for hash in
+                fetch_block_hashes_by_prefix(&database, &start, end, MAX_SUGGESTIONS).await?
+            {
+                suggestions.push(SearchResult::block(&hash));
+            }
+
+            for txid in fetch_txids_by_prefix(&database, &start, end, MAX_SUGGESTIONS).await? {
+                suggestions.push(SearchResult::transaction(&txid));
+            }
+        }
+
+        if q.chars().all(|c| c.is_ascii_alphanumeric()) {
+            for address in fetch_addresses_by_prefix(&database, q, MAX_SUGGESTIONS).await? {
+                suggestions.push(SearchResult::address(address));
+            }
+        }
+    }
+
+    Ok(Json(SearchResponse {
+        matches,
+        suggestions,
+    }))
+}
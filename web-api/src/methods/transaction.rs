@@ -1,7 +1,16 @@
-use crate::database::transactions::{fetch_latest_transactions, fetch_transaction_by_hash};
-use crate::{methods::block::Transaction, Database};
+use crate::database::{
+    blocks::fetch_height,
+    transactions::{fetch_latest_transactions, fetch_transaction_by_hash},
+};
+use crate::{
+    error::ApiError,
+    hash,
+    methods::block::{confirmations, Transaction},
+    Database,
+};
 use axum::extract::Query;
 use axum::{extract::Path, Extension, Json};
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
@@ -21,39 +30,63 @@ pub struct ListResponseTransaction {
 pub async fn list(
     Extension(database): Extension<Database>,
     Query(query): Query<ListQuery>,
-) -> Json<Vec<ListResponseTransaction>> {
-    let database = database.get().await.unwrap();
+) -> Result<Json<Vec<ListResponseTransaction>>, ApiError> {
+    let database = database.get().await?;
 
-    let limit = std::cmp::min(20, std::cmp::max(5, query.limit));
+    let limit = query.limit.clamp(5, 20);
 
-    let transactions = fetch_latest_transactions(&database, limit.into())
-        .await
-        .unwrap();
+    let transactions = fetch_latest_transactions(&database, limit.into()).await?;
 
-    Json(
+    Ok(Json(
         transactions
             .into_iter()
             .map(|v| ListResponseTransaction {
                 transaction: v.transaction.into(),
-                input_total_value: i64::try_from(v.input_total_value.mantissa()).unwrap(),
-                output_total_value: i64::try_from(v.output_total_value.mantissa()).unwrap(),
+                input_total_value: v.input_total_value,
+                output_total_value: v.output_total_value,
             })
             .collect(),
-    )
+    ))
+}
+
+#[derive(Serialize)]
+pub struct TransactionStatus {
+    confirmed: bool,
+    block_height: i64,
+    block_hash: String,
+    block_time: NaiveDateTime,
+    confirmations: i64,
+}
+
+#[derive(Serialize)]
+pub struct TransactionResponse {
+    #[serde(flatten)]
+    transaction: Transaction,
+    status: TransactionStatus,
 }
 
 pub async fn handle(
     Extension(database): Extension<Database>,
     Path(hash): Path<String>,
-) -> Json<Transaction> {
-    let mut hash = hex::decode(&hash).unwrap();
-    hash.reverse();
+) -> Result<Json<TransactionResponse>, ApiError> {
+    let hash = hash::decode(&hash).ok_or(ApiError::BadRequest("transaction hash"))?;
 
-    let database = database.get().await.unwrap();
+    let database = database.get().await?;
     let transaction = fetch_transaction_by_hash(&database, &hash)
-        .await
-        .unwrap()
-        .unwrap();
+        .await?
+        .ok_or(ApiError::NotFound("Transaction"))?;
+
+    let tip = fetch_height(&database).await?;
 
-    Json(transaction.into())
+    // we only index transactions once they've been mined, so every one we know of is confirmed
+    Ok(Json(TransactionResponse {
+        status: TransactionStatus {
+            confirmed: true,
+            block_height: transaction.block_height,
+            block_hash: hash::encode(&transaction.block_hash),
+            block_time: transaction.block_timestamp,
+            confirmations: confirmations(tip, transaction.block_height),
+        },
+        transaction: transaction.transaction.into(),
+    }))
 }